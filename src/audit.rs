@@ -0,0 +1,203 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! An append-only record of logins and changes to stored files, one JSON
+//! object per line. With hash chaining, each entry carries the SHA-256 of
+//! itself and the hash of the entry before it, so that removed or altered
+//! entries can be detected with [`verify`].
+
+use crate::utils::config::AuditConfig;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    io::{self, SeekFrom},
+    path::Path,
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::Mutex,
+};
+
+/// How an audited action ended.
+pub enum Outcome {
+    Success,
+    Failure(String),
+    /// Refused for lack of permission.
+    Denied(String),
+}
+
+impl<T> From<&io::Result<T>> for Outcome {
+    fn from(result: &io::Result<T>) -> Self {
+        match result {
+            Ok(_) => Outcome::Success,
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                Outcome::Denied(err.to_string())
+            }
+            Err(err) => Outcome::Failure(err.to_string()),
+        }
+    }
+}
+
+/// Something a session did, to be recorded.
+pub struct Event<'a> {
+    pub session_id: u64,
+    pub user: &'a str,
+    pub peer: &'a str,
+    pub action: &'a str,
+    pub path: Option<&'a Path>,
+    /// The new name, for renames.
+    pub to: Option<&'a Path>,
+    pub outcome: Outcome,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    time: String,
+    session_id: u64,
+    user: String,
+    peer: String,
+    action: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+    result: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prev: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+}
+
+impl Entry {
+    /// The hash of the entry as serialized without its own hash.
+    fn digest(&self) -> io::Result<String> {
+        let unhashed = Entry {
+            hash: None,
+            ..self.clone()
+        };
+        let json = serde_json::to_vec(&unhashed)?;
+        Ok(hex::encode(Sha256::digest(&json)))
+    }
+}
+
+pub struct AuditLog {
+    state: Mutex<State>,
+}
+
+struct State {
+    file: File,
+    chained: bool,
+    /// Hash of the last entry written, when chaining.
+    last_hash: Option<String>,
+}
+
+impl AuditLog {
+    /// Open the log for appending. When chaining, the chain continues from
+    /// the last entry already in the file.
+    pub async fn open(config: &AuditConfig) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&config.path)
+            .await?;
+        let last_hash = if config.hash_chain {
+            last_hash(&mut file).await?
+        } else {
+            None
+        };
+        Ok(Self {
+            state: Mutex::new(State {
+                file,
+                chained: config.hash_chain,
+                last_hash,
+            }),
+        })
+    }
+
+    pub async fn record(&self, event: Event<'_>) -> io::Result<()> {
+        let (result, reason) = match event.outcome {
+            Outcome::Success => ("success", None),
+            Outcome::Failure(reason) => ("failure", Some(reason)),
+            Outcome::Denied(reason) => ("denied", Some(reason)),
+        };
+        let mut entry = Entry {
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            session_id: event.session_id,
+            user: event.user.to_string(),
+            peer: event.peer.to_string(),
+            action: event.action.to_string(),
+            path: event.path.map(|x| x.to_string_lossy().into_owned()),
+            to: event.to.map(|x| x.to_string_lossy().into_owned()),
+            result: result.to_string(),
+            reason,
+            prev: None,
+            hash: None,
+        };
+        let mut state = self.state.lock().await;
+        if state.chained {
+            entry.prev = state.last_hash.clone();
+            entry.hash = Some(entry.digest()?);
+        }
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        state.file.write_all(&line).await?;
+        state.file.flush().await?;
+        if state.chained {
+            state.last_hash = entry.hash;
+        }
+        Ok(())
+    }
+}
+
+/// Find the hash of the last entry, reading only the end of the file.
+async fn last_hash(file: &mut File) -> io::Result<Option<String>> {
+    const TAIL: u64 = 64 << 10;
+    let len = file.metadata().await?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL))).await?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).await?;
+    let last = match tail.split(|&x| x == b'\n').rfind(|x| !x.is_empty()) {
+        Some(last) => last,
+        None => return Ok(None),
+    };
+    let entry: Entry = serde_json::from_slice(last).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("last audit entry is unreadable: {}", err),
+        )
+    })?;
+    Ok(entry.hash)
+}
+
+/// Check the hash chain of the audit log at `path`, returning the number of
+/// entries, or a description of the first entry that does not fit.
+pub fn verify(path: &Path) -> Result<usize, String> {
+    let content = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mut last_hash: Option<String> = None;
+    let mut count = 0;
+    for (number, line) in content.lines().enumerate() {
+        let number = number + 1;
+        let entry: Entry = serde_json::from_str(line)
+            .map_err(|err| format!("line {}: unreadable: {}", number, err))?;
+        let hash = entry
+            .hash
+            .clone()
+            .ok_or_else(|| format!("line {}: no hash", number))?;
+        if entry.prev != last_hash {
+            return Err(format!(
+                "line {}: does not follow the entry before it",
+                number
+            ));
+        }
+        if entry.digest().map_err(|err| err.to_string())? != hash {
+            return Err(format!("line {}: hash does not match", number));
+        }
+        last_hash = Some(hash);
+        count += 1;
+    }
+    Ok(count)
+}
@@ -0,0 +1,208 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Commands and webhooks run in the background once files have changed.
+
+use crate::utils::config::{Hook, HookEvent, HooksConfig};
+use slog::{info, warn, Logger};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    process::Command,
+    sync::Semaphore,
+};
+
+/// Longest webhook response read, status line included.
+const MAX_RESPONSE: u64 = 64 << 10;
+
+/// Runs hooks, a limited number at a time across all sessions, with a
+/// limited number more waiting.
+#[derive(Clone)]
+pub(crate) struct Hooks {
+    permits: Arc<Semaphore>,
+    queue: Arc<Semaphore>,
+}
+
+/// A change to a file, as told to hooks.
+#[derive(Clone)]
+pub(crate) struct Event {
+    pub kind: HookEvent,
+    pub path: PathBuf,
+    /// The old path, for renames.
+    pub from: Option<PathBuf>,
+    /// Where `path` and `from` are on the local filesystem, when files are
+    /// kept there.
+    pub real_path: Option<PathBuf>,
+    pub real_from: Option<PathBuf>,
+    pub user: String,
+    pub peer: String,
+    pub size: Option<u64>,
+}
+
+impl Hooks {
+    pub(crate) fn new(max_running: usize, max_queued: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_running.max(1))),
+            queue: Arc::new(Semaphore::new(max_running.max(1) + max_queued)),
+        }
+    }
+
+    /// Start the hooks of `config` that want `event`, logging how each ends
+    /// to `logger`.
+    pub(crate) fn run(&self, config: &HooksConfig, event: &Event, logger: &Logger) {
+        let timeout = Duration::from_secs(config.timeout);
+        for hook in &config.run {
+            let events = match hook {
+                Hook::Command { events, .. } | Hook::Webhook { events, .. } => events,
+            };
+            if !events.is_empty() && !events.contains(&event.kind) {
+                continue;
+            }
+            let name = match hook {
+                Hook::Command { command, .. } => command.display().to_string(),
+                Hook::Webhook { url, .. } => url.clone(),
+            };
+            // A place among the hooks running or waiting, held until done.
+            let place = match self.queue.clone().try_acquire_owned() {
+                Ok(place) => place,
+                Err(_) => {
+                    warn!(
+                        logger,
+                        "Hook {} dropped for {:?}: too many hooks waiting.", name, event.path
+                    );
+                    continue;
+                }
+            };
+            let (hook, event, logger) = (hook.clone(), event.clone(), logger.clone());
+            let permits = self.permits.clone();
+            tokio::spawn(async move {
+                let _place = place;
+                let _permit = match permits.acquire_owned().await {
+                    Ok(permit) => permit,
+                    Err(_) => return,
+                };
+                let finished = match &hook {
+                    Hook::Command { command, args, .. } => {
+                        tokio::time::timeout(timeout, run_command(command, args, &event)).await
+                    }
+                    Hook::Webhook { url, .. } => {
+                        tokio::time::timeout(timeout, post(url, &event)).await
+                    }
+                };
+                match finished {
+                    Ok(Ok(_)) => info!(logger, "Hook {} done for {:?}.", name, event.path),
+                    Ok(Err(err)) => {
+                        warn!(logger, "Hook {} failed for {:?}: {}", name, event.path, err)
+                    }
+                    Err(_) => warn!(
+                        logger,
+                        "Hook {} for {:?} timed out after {}s.",
+                        name,
+                        event.path,
+                        timeout.as_secs()
+                    ),
+                }
+            });
+        }
+    }
+}
+
+fn label(kind: HookEvent) -> &'static str {
+    match kind {
+        HookEvent::Upload => "upload",
+        HookEvent::Append => "append",
+        HookEvent::Rename => "rename",
+        HookEvent::Delete => "delete",
+    }
+}
+
+async fn run_command(command: &Path, args: &[String], event: &Event) -> io::Result<()> {
+    let mut command = Command::new(command);
+    command
+        .args(args)
+        .env("KIRAFTP_EVENT", label(event.kind))
+        .env("KIRAFTP_PATH", &event.path)
+        .env("KIRAFTP_USER", &event.user)
+        .env("KIRAFTP_PEER", &event.peer)
+        .stdin(Stdio::null())
+        // Timing out drops the child, which should not outlive the hook.
+        .kill_on_drop(true);
+    if let Some(from) = &event.from {
+        command.env("KIRAFTP_FROM", from);
+    }
+    if let Some(real_path) = &event.real_path {
+        command.env("KIRAFTP_REAL_PATH", real_path);
+    }
+    if let Some(real_from) = &event.real_from {
+        command.env("KIRAFTP_REAL_FROM", real_from);
+    }
+    if let Some(size) = event.size {
+        command.env("KIRAFTP_SIZE", size.to_string());
+    }
+    let output = command.output().await?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// POST `event` to `url` as JSON, expecting a 2xx status.
+async fn post(url: &str, event: &Event) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "not an http:// URL");
+    let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+    let (authority, path) = match rest.find('/') {
+        Some(at) => rest.split_at(at),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() {
+        return Err(invalid());
+    }
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    let mut stream = if has_port {
+        TcpStream::connect(authority).await?
+    } else {
+        TcpStream::connect((authority.trim_matches(['[', ']']), 80)).await?
+    };
+    let body = serde_json::json!({
+        "event": label(event.kind),
+        "path": event.path,
+        "from": event.from,
+        "real_path": event.real_path,
+        "real_from": event.real_from,
+        "user": event.user,
+        "peer": event.peer,
+        "size": event.size,
+    })
+    .to_string();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE).read_to_end(&mut response).await?;
+    let status = String::from_utf8_lossy(&response);
+    let status = status.lines().next().unwrap_or_default();
+    match status.split(' ').nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!("response {:?}", status))),
+    }
+}
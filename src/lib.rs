@@ -0,0 +1,15 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! A crude FTP server, embeddable in any tokio runtime.
+
+pub mod audit;
+mod hooks;
+pub mod metrics;
+mod server;
+pub mod session;
+pub mod storage;
+pub mod utils;
+
+pub use server::{ReloadHandle, Server, ServerBuilder, ShutdownHandle};
+pub use session::FTPSession;
@@ -1,74 +1,280 @@
 // Copyright 2021 Slowy <slowyfine@gmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
-mod session;
-mod utils;
+use clap::{Args, Parser, Subcommand};
+use kiraftp::{
+    utils::{config::Config, log},
+    ReloadHandle, Server,
+};
+use slog::{error, info, warn, Level, Logger};
+use std::{net::IpAddr, path::PathBuf, process::ExitCode, time::Duration};
+use tokio::signal::unix::{signal, SignalKind};
 
-use session::FTPSession;
-use slog::{error, info, o, warn, Drain, Logger};
-use slog_async::Async;
-use slog_term::{CompactFormat, TermDecorator};
-use std::sync::Arc;
-use tokio::net::TcpListener;
-use utils::config::Config;
+const CONFIG_PATH: &str = "config.yaml";
+
+/// A crude FTP server.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(flatten)]
+    options: Options,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve files (the default).
+    Serve,
+    /// Check the config and exit.
+    CheckConfig,
+    /// Print the effective config with secrets redacted.
+    PrintConfig,
+    /// Print a config file with the default settings.
+    PrintDefaultConfig,
+    /// Check the hash chain of an audit log and exit.
+    VerifyAuditLog {
+        /// The audit log to check.
+        path: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct Options {
+    /// Config file to read, YAML or TOML. Defaults to config.yaml when it
+    /// exists. Settings can also be given as KIRAFTP_* environment variables.
+    #[arg(short, long, global = true, env = "KIRAFTP_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on, overriding the config file.
+    #[arg(long, global = true)]
+    listen: Option<IpAddr>,
+    /// Port to listen on, overriding the config file.
+    #[arg(long, global = true)]
+    port: Option<u16>,
+    /// Directory to serve, overriding the config file.
+    #[arg(long, global = true)]
+    root: Option<PathBuf>,
+    /// Lowest level to log: critical, error, warn, info, debug or trace,
+    /// overriding the config file.
+    #[arg(long, global = true, value_parser = parse_level)]
+    log_level: Option<String>,
+    /// Stay in the foreground. kiraftp never daemonizes, so this is always
+    /// the case; it is accepted for service managers that pass it.
+    #[arg(long, global = true)]
+    foreground: bool,
+}
+
+fn parse_level(level: &str) -> Result<String, String> {
+    match level.parse::<Level>() {
+        Ok(_) => Ok(level.to_lowercase()),
+        Err(_) => Err(format!("unknown log level {:?}", level)),
+    }
+}
+
+impl Options {
+    fn config_file(&self) -> Option<PathBuf> {
+        match &self.config {
+            Some(path) => Some(path.clone()),
+            None => Some(PathBuf::from(CONFIG_PATH)).filter(|x| x.exists()),
+        }
+    }
+
+    /// Apply the overrides given on the command line.
+    fn apply(&self, config: &mut Config) {
+        if let Some(listen) = self.listen {
+            config.listen = listen;
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(root) = &self.root {
+            config.path = root.clone();
+        }
+        if let Some(level) = &self.log_level {
+            config.log.level = level.clone();
+        }
+    }
+}
 
 #[tokio::main]
-async fn main() {
-    let logger = {
-        let decorator = TermDecorator::new().build();
-        let drain = CompactFormat::new(decorator).build().fuse();
-        let drain = Async::new(drain).build().fuse();
-        Arc::new(Logger::root(drain, o!()))
-    };
-    info!(logger, "Start logging!");
-    let config = match tokio::fs::read("config.yaml").await {
-        Ok(config) => match serde_yaml::from_slice(&config) {
-            Ok(config) => config,
-            Err(_) => {
-                warn!(
-                    logger,
-                    "Failed to parse config.yaml, fall back to default config."
-                );
-                Config::default()
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(cli.options).await,
+        Command::CheckConfig => check_config(&cli.options).await,
+        Command::PrintConfig => match read_config(&cli.options).await {
+            Ok(config) => {
+                print!("{}", config.redacted());
+                ExitCode::SUCCESS
+            }
+            Err(problems) => {
+                for problem in problems {
+                    eprintln!("Invalid config: {}", problem);
+                }
+                ExitCode::FAILURE
+            }
+        },
+        Command::PrintDefaultConfig => match serde_yaml::to_string(&Config::default()) {
+            Ok(config) => {
+                print!("{}", config.trim_start_matches("---\n"));
+                ExitCode::SUCCESS
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                ExitCode::FAILURE
             }
         },
-        Err(_) => {
-            info!(logger, "Use default config.");
-            Config::default()
+        Command::VerifyAuditLog { path } => match kiraftp::audit::verify(&path) {
+            Ok(count) => {
+                println!("{} entries verified.", count);
+                ExitCode::SUCCESS
+            }
+            Err(problem) => {
+                eprintln!("Audit log does not verify: {}", problem);
+                ExitCode::FAILURE
+            }
+        },
+    }
+}
+
+async fn serve(options: Options) -> ExitCode {
+    let config = match read_config(&options).await {
+        Ok(config) => config,
+        Err(problems) => {
+            for problem in problems {
+                eprintln!("Invalid config: {}", problem);
+            }
+            return ExitCode::FAILURE;
         }
     };
-    let config = Arc::new(config);
-    match TcpListener::bind(config.address()).await {
+    // The guard flushes pending records when this returns.
+    let (logger, _guard) = match log::build(&config.log) {
+        Ok(logger) => logger,
+        Err(err) => {
+            eprintln!("Failed to set up logging: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    info!(logger, "Start logging!");
+    for name in Config::unknown_vars(std::env::vars()) {
+        warn!(logger, "Ignoring {}, which names no setting.", name);
+    }
+    let watch_config = config.watch_config;
+    let server = Server::builder()
+        .config(config)
+        .logger(logger.clone())
+        .build()
+        .await;
+    match server {
         Ok(server) => {
-            info!(logger, "Listening {}:{}", config.listen, config.port);
-            loop {
-                match server.accept().await {
-                    Ok((stream, remote)) => {
-                        info!(logger, "Connection from {} was established.", remote.ip());
-                        let mut session = FTPSession::new(stream, logger.clone(), config.clone());
-                        tokio::spawn(async move {
-                            match session.run().await {
-                                Ok(_) => {
-                                    info!(
-                                        session.logger,
-                                        "Connection from {} was closed.",
-                                        remote.ip()
-                                    )
-                                }
-                                Err(err) => {
-                                    error!(session.logger, "Unexpected connection closed: {}", err)
-                                }
-                            }
-                        });
-                    }
-                    Err(err) => {
-                        error!(logger, "Unexpected connection: {}", err);
+            tokio::spawn(reload_config(
+                server.reload_handle(),
+                options,
+                watch_config,
+                logger.clone(),
+            ));
+            let shutdown = server.shutdown_handle();
+            let logger = logger.clone();
+            tokio::spawn(async move {
+                match wait_for_signal().await {
+                    Ok(signal) => {
+                        info!(logger, "Received {}, shutting down.", signal);
+                        shutdown.shutdown();
                     }
+                    Err(err) => error!(logger, "Failed to listen for signals: {}", err),
                 }
-            }
+            });
+            server.run().await;
+            ExitCode::SUCCESS
         }
         Err(err) => {
             error!(logger, "Failed to Listening: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn check_config(options: &Options) -> ExitCode {
+    for name in Config::unknown_vars(std::env::vars()) {
+        eprintln!("Ignoring {}, which names no setting.", name);
+    }
+    match read_config(options).await {
+        Ok(_) => {
+            println!("Config OK");
+            ExitCode::SUCCESS
+        }
+        Err(problems) => {
+            for problem in problems {
+                eprintln!("Invalid config: {}", problem);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn wait_for_signal() -> tokio::io::Result<&'static str> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    Ok(tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    })
+}
+
+/// Load the config file and environment, apply the command line overrides and
+/// validate the result, describing every problem on failure.
+async fn read_config(options: &Options) -> Result<Config, Vec<String>> {
+    let file = options.config_file();
+    let vars: Vec<_> = std::env::vars().collect();
+    let mut config = tokio::task::spawn_blocking(move || Config::load(file.as_deref(), vars))
+        .await
+        .map_err(|err| vec![err.to_string()])?
+        .map_err(|err| vec![err])?;
+    options.apply(&mut config);
+    config.validate()?;
+    Ok(config)
+}
+
+async fn modified(path: Option<PathBuf>) -> Option<std::time::SystemTime> {
+    tokio::fs::metadata(path?).await.ok()?.modified().ok()
+}
+
+/// Reload the config on SIGHUP, and when `watch` is set, whenever the file
+/// changes. A config that fails to load leaves the running one in place.
+async fn reload_config(reload: ReloadHandle, options: Options, watch: bool, logger: Logger) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!(logger, "Failed to listen for SIGHUP: {}", err);
+            return;
+        }
+    };
+    let mut ticker = tokio::time::interval(Duration::from_secs(2));
+    let mut last_modified = modified(options.config_file()).await;
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => info!(logger, "Received SIGHUP, reloading config."),
+            _ = ticker.tick(), if watch => {
+                let now = modified(options.config_file()).await;
+                if now == last_modified {
+                    continue;
+                }
+                last_modified = now;
+                info!(logger, "Config file changed, reloading.");
+            }
+        }
+        match read_config(&options).await {
+            Ok(config) => {
+                if let Err(err) = reload.reload(config) {
+                    error!(logger, "Failed to apply config: {}", err);
+                }
+            }
+            Err(problems) => {
+                for problem in problems {
+                    error!(logger, "Invalid config: {}", problem);
+                }
+                warn!(logger, "Keeping the current config.");
+            }
         }
     }
 }
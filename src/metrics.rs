@@ -0,0 +1,201 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Counters describing server load, served in the Prometheus text format.
+
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Verbs counted by name. Anything else is counted as `OTHER`, so that
+/// clients cannot create an unbounded number of series.
+const VERBS: &[&str] = &[
+    "ALLO", "APPE", "CWD", "DELE", "EPRT", "FEAT", "HASH", "LIST", "MKD", "MODE", "NOOP", "OPTS",
+    "PASS", "PASV", "PORT", "PWD", "QUIT", "RANG", "REST", "RETR", "RMD", "RNFR", "RNTO", "SITE",
+    "STOR", "STRU", "SYST", "TYPE", "USER", "XCRC", "XMD5", "XSHA1", "XSHA256",
+];
+
+pub struct Metrics {
+    registry: Registry,
+    sessions: IntGauge,
+    logins: IntCounterVec,
+    commands: IntCounterVec,
+    bytes: IntCounterVec,
+    transfer_duration: HistogramVec,
+    passive_ports: IntGauge,
+}
+
+/// Direction of a transfer, as seen from the server.
+#[derive(Clone, Copy)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+impl Direction {
+    fn label(self) -> &'static str {
+        match self {
+            Direction::Upload => "upload",
+            Direction::Download => "download",
+        }
+    }
+}
+
+/// Counts something as in use until dropped.
+pub struct InUse(IntGauge);
+
+impl Drop for InUse {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let sessions = IntGauge::new("kiraftp_sessions", "Control connections open.").unwrap();
+        let logins = IntCounterVec::new(
+            Opts::new("kiraftp_logins_total", "Login attempts by result."),
+            &["result"],
+        )
+        .unwrap();
+        let commands = IntCounterVec::new(
+            Opts::new(
+                "kiraftp_commands_total",
+                "Commands handled, by verb and final reply code.",
+            ),
+            &["verb", "code"],
+        )
+        .unwrap();
+        let bytes = IntCounterVec::new(
+            Opts::new(
+                "kiraftp_transfer_bytes_total",
+                "Bytes sent over data connections, by direction.",
+            ),
+            &["direction"],
+        )
+        .unwrap();
+        let transfer_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "kiraftp_transfer_duration_seconds",
+                "Time taken by RETR and STOR transfers, by direction.",
+            )
+            .buckets(vec![0.01, 0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0, 1800.0]),
+            &["direction"],
+        )
+        .unwrap();
+        let passive_ports = IntGauge::new(
+            "kiraftp_passive_ports",
+            "Passive mode ports listening for a data connection.",
+        )
+        .unwrap();
+        let registry = Registry::new();
+        registry.register(Box::new(sessions.clone())).unwrap();
+        registry.register(Box::new(logins.clone())).unwrap();
+        registry.register(Box::new(commands.clone())).unwrap();
+        registry.register(Box::new(bytes.clone())).unwrap();
+        registry
+            .register(Box::new(transfer_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(passive_ports.clone())).unwrap();
+        Self {
+            registry,
+            sessions,
+            logins,
+            commands,
+            bytes,
+            transfer_duration,
+            passive_ports,
+        }
+    }
+
+    pub fn session(&self) -> InUse {
+        self.sessions.inc();
+        InUse(self.sessions.clone())
+    }
+
+    pub fn passive_port(&self) -> InUse {
+        self.passive_ports.inc();
+        InUse(self.passive_ports.clone())
+    }
+
+    pub fn login(&self, succeeded: bool) {
+        let result = if succeeded { "success" } else { "failure" };
+        self.logins.with_label_values(&[result]).inc();
+    }
+
+    /// Count a command line, given its first word and the code of the last
+    /// reply sent for it.
+    pub fn command(&self, verb: &str, code: &str) {
+        let verb = VERBS
+            .iter()
+            .find(|x| x.eq_ignore_ascii_case(verb))
+            .unwrap_or(&"OTHER");
+        self.commands.with_label_values(&[verb, code]).inc();
+    }
+
+    pub fn transfer(&self, direction: Direction, bytes: u64, duration: Duration) {
+        self.bytes
+            .with_label_values(&[direction.label()])
+            .inc_by(bytes);
+        self.transfer_duration
+            .with_label_values(&[direction.label()])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// All metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .unwrap_or_default()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Answer `GET /metrics` on `listener` until the future is dropped.
+pub async fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    loop {
+        if let Ok((stream, _)) = listener.accept().await {
+            let metrics = metrics.clone();
+            tokio::spawn(async move {
+                let respond = respond(stream, &metrics);
+                let _ = tokio::time::timeout(Duration::from_secs(10), respond).await;
+            });
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, metrics: &Metrics) -> tokio::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+        let len = stream.read(&mut buffer).await?;
+        if len == 0 || request.len() > 8192 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..len]);
+    }
+    let (status, body) = if request.starts_with(b"GET /metrics ") {
+        ("200 OK", metrics.render())
+    } else {
+        ("404 Not Found", String::from("Not found.\n"))
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
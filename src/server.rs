@@ -0,0 +1,361 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    audit::AuditLog,
+    hooks::Hooks,
+    metrics::{self, Metrics},
+    session::{DiskUsage, FTPSession, PassivePorts},
+    storage::{self, StorageBackend},
+    utils::config::Config,
+};
+use slog::{error, info, o, warn, Discard, Logger};
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::{net::TcpListener, sync::watch, task::JoinSet};
+
+/// An FTP server accepting connections on a single listener.
+///
+/// ```no_run
+/// # async fn example() -> tokio::io::Result<()> {
+/// use kiraftp::{utils::config::Config, Server};
+///
+/// let config = Config {
+///     username: String::from("alice"),
+///     password: String::from("secret"),
+///     path: "/srv/ftp".into(),
+///     ..Config::default()
+/// };
+/// let server = Server::builder().config(config).build().await?;
+/// let shutdown = server.shutdown_handle();
+/// tokio::spawn(server.run());
+/// // ...
+/// shutdown.shutdown();
+/// # Ok(())
+/// # }
+/// ```
+pub struct Server {
+    listener: TcpListener,
+    metrics_listener: Option<TcpListener>,
+    metrics: Arc<Metrics>,
+    passive_ports: PassivePorts,
+    audit_log: Option<Arc<AuditLog>>,
+    hooks: Hooks,
+    logger: Arc<Logger>,
+    current: Arc<RwLock<Current>>,
+    custom_storage: bool,
+    shutdown: Arc<watch::Sender<bool>>,
+    shutdown_signal: watch::Receiver<bool>,
+}
+
+/// What new sessions are started with. Replaced as a whole on reload.
+struct Current {
+    config: Arc<Config>,
+    storage: Arc<dyn StorageBackend>,
+    disk_usage: DiskUsage,
+}
+
+/// Swaps the configuration of a running [`Server`]. Only sessions started
+/// afterwards see the new configuration.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    current: Arc<RwLock<Current>>,
+    custom_storage: bool,
+    logger: Arc<Logger>,
+}
+
+/// Asks a running [`Server`] to stop accepting connections and close its
+/// sessions once they are idle.
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<watch::Sender<bool>>);
+
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: Option<Config>,
+    listener: Option<TcpListener>,
+    logger: Option<Logger>,
+    storage: Option<Arc<dyn StorageBackend>>,
+}
+
+impl ServerBuilder {
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Accept connections on an already bound listener instead of binding
+    /// the address from the config.
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    /// Serve files from `storage` instead of the backend selected in the
+    /// config.
+    pub fn storage(mut self, storage: Arc<dyn StorageBackend>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Fails when no config was given, or it has no credentials.
+    pub async fn build(self) -> tokio::io::Result<Server> {
+        let config = self
+            .config
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no config was given"))?;
+        if config.username.is_empty() || config.password.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the config has no username or password",
+            ));
+        }
+        let listener = match self.listener {
+            Some(listener) => listener,
+            None => TcpListener::bind(config.address()).await?,
+        };
+        let metrics_listener = match config.metrics {
+            Some(address) => Some(TcpListener::bind(address).await?),
+            None => None,
+        };
+        let audit_log = match &config.audit {
+            Some(audit) => Some(Arc::new(AuditLog::open(audit).await?)),
+            None => None,
+        };
+        let hooks = Hooks::new(config.hooks.max_running, config.hooks.max_queued);
+        let logger = self.logger.unwrap_or_else(|| Logger::root(Discard, o!()));
+        let custom_storage = self.storage.is_some();
+        let storage = match self.storage {
+            Some(storage) => storage,
+            None => storage::from_config(&config)?,
+        };
+        let (shutdown, shutdown_signal) = watch::channel(false);
+        Ok(Server {
+            listener,
+            metrics_listener,
+            metrics: Arc::default(),
+            passive_ports: PassivePorts::default(),
+            audit_log,
+            hooks,
+            logger: Arc::new(logger),
+            current: Arc::new(RwLock::new(Current {
+                config: Arc::new(config),
+                storage,
+                disk_usage: DiskUsage::default(),
+            })),
+            custom_storage,
+            shutdown: Arc::new(shutdown),
+            shutdown_signal,
+        })
+    }
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    pub fn local_addr(&self) -> tokio::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Where the metrics endpoint listens, if it is enabled.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics_listener.as_ref()?.local_addr().ok()
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    pub fn reload_handle(&self) -> ReloadHandle {
+        ReloadHandle {
+            current: self.current.clone(),
+            custom_storage: self.custom_storage,
+            logger: self.logger.clone(),
+        }
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.shutdown.clone())
+    }
+
+    /// Accept connections until shutdown is requested, then wait for the
+    /// sessions to finish, up to `shutdown_timeout` seconds. Idle sessions
+    /// are told the service is closing; transfers in flight are given until
+    /// the deadline before they are aborted.
+    pub async fn run(mut self) {
+        info!(
+            self.logger,
+            "Listening {}",
+            self.listener.local_addr().unwrap()
+        );
+        let endpoint = self.metrics_listener.take().map(|listener| {
+            info!(
+                self.logger,
+                "Serving metrics on {}",
+                listener.local_addr().unwrap()
+            );
+            tokio::spawn(metrics::serve(listener, self.metrics.clone()))
+        });
+        let mut sessions = JoinSet::new();
+        let mut session_id: u64 = 0;
+        while !*self.shutdown_signal.borrow() {
+            tokio::select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, remote)) => {
+                        session_id += 1;
+                        let logger = self.logger.new(o!(
+                            "session_id" => session_id,
+                            "peer" => remote.to_string(),
+                        ));
+                        info!(logger, "Connection from {} was established.", remote.ip());
+                        let (config, storage, disk_usage) = {
+                            let current = self.current.read().unwrap();
+                            (
+                                current.config.clone(),
+                                current.storage.clone(),
+                                current.disk_usage.clone(),
+                            )
+                        };
+                        let mut session =
+                            FTPSession::new(stream, Arc::new(logger), config, storage);
+                        session.set_shutdown_signal(self.shutdown_signal.clone());
+                        session.set_metrics(self.metrics.clone());
+                        session.set_passive_ports(self.passive_ports.clone());
+                        session.set_disk_usage(disk_usage);
+                        session.set_hooks(self.hooks.clone());
+                        if let Some(audit_log) = &self.audit_log {
+                            session.set_audit_log(audit_log.clone(), session_id);
+                        }
+                        sessions.spawn(async move {
+                            match session.run().await {
+                                Ok(_) => {
+                                    info!(
+                                        session.logger,
+                                        "Connection from {} was closed.",
+                                        remote.ip()
+                                    )
+                                }
+                                Err(err) => {
+                                    error!(session.logger, "Unexpected connection closed: {}", err)
+                                }
+                            }
+                        });
+                    }
+                    Err(err) => {
+                        error!(self.logger, "Unexpected connection: {}", err);
+                    }
+                },
+                Some(_) = sessions.join_next(), if !sessions.is_empty() => {}
+                _ = self.shutdown_signal.changed() => {}
+            }
+        }
+        drop(self.listener);
+        let total = sessions.len();
+        info!(
+            self.logger,
+            "Stop listening, waiting for {} sessions.", total
+        );
+        let deadline = Duration::from_secs(self.current.read().unwrap().config.shutdown_timeout);
+        let drain = async { while sessions.join_next().await.is_some() {} };
+        let mut aborted = 0;
+        if tokio::time::timeout(deadline, drain).await.is_err() {
+            aborted = sessions.len();
+            warn!(
+                self.logger,
+                "Aborting {} sessions still busy after {}s.",
+                aborted,
+                deadline.as_secs()
+            );
+            sessions.shutdown().await;
+        }
+        if let Some(endpoint) = endpoint {
+            endpoint.abort();
+        }
+        info!(
+            self.logger,
+            "Shutdown complete: {} sessions closed, {} aborted.",
+            total - aborted,
+            aborted
+        );
+    }
+}
+
+impl ReloadHandle {
+    /// Use `config` for sessions started from now on, logging what changed.
+    /// The storage backend is recreated when its settings changed, unless
+    /// it was given to the builder. The listening address is kept as is.
+    ///
+    /// A config that fails [`Config::validate`] is refused, with every
+    /// problem in the error, and the current one kept.
+    pub fn reload(&self, config: Config) -> tokio::io::Result<()> {
+        if let Err(problems) = config.validate() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                problems.join("; "),
+            ));
+        }
+        let old = self.current.read().unwrap().config.clone();
+        let changes = old.changes(&config);
+        if changes.is_empty() {
+            info!(self.logger, "Configuration reloaded, nothing changed.");
+            return Ok(());
+        }
+        let storage_changed = old.storage != config.storage || old.path != config.path;
+        let storage = if storage_changed && !self.custom_storage {
+            Some(storage::from_config(&config)?)
+        } else {
+            None
+        };
+        if old.address() != config.address() {
+            warn!(
+                self.logger,
+                "Listening address changes take effect after restart."
+            );
+        }
+        if old.metrics != config.metrics {
+            warn!(
+                self.logger,
+                "Metrics address changes take effect after restart."
+            );
+        }
+        if old.log != config.log {
+            warn!(self.logger, "Logging changes take effect after restart.");
+        }
+        if old.hooks.max_running != config.hooks.max_running
+            || old.hooks.max_queued != config.hooks.max_queued
+        {
+            warn!(
+                self.logger,
+                "Hook concurrency changes take effect after restart."
+            );
+        }
+        if old.audit != config.audit {
+            warn!(self.logger, "Audit log changes take effect after restart.");
+        }
+        for change in changes {
+            info!(self.logger, "Configuration changed: {}", change);
+        }
+        let mut current = self.current.write().unwrap();
+        current.config = Arc::new(config);
+        if let Some(storage) = storage {
+            current.storage = storage;
+            current.disk_usage = DiskUsage::default();
+        }
+        Ok(())
+    }
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        let _ = self.0.send(true);
+    }
+}
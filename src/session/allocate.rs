@@ -0,0 +1,40 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use tokio::io::AsyncWriteExt;
+
+impl FTPSession {
+    /// Take the size of the next upload, so that one too large for the user
+    /// is refused before any data is sent. A record size may follow, as in
+    /// `ALLO 1000 R 100`, and is ignored.
+    pub async fn allocate(&mut self, para: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        let size = match para.split(' ').next().map(str::parse::<u64>) {
+            Some(Ok(size)) => size,
+            _ => {
+                self.control_stream
+                    .write_all(b"501 Invalid size.\r\n")
+                    .await?;
+                return Ok(());
+            }
+        };
+        if self.max_upload_size().is_some_and(|max| size > max) {
+            self.allocation = None;
+            self.control_stream
+                .write_all(b"552 File exceeds the maximum upload size.\r\n")
+                .await?;
+            return Ok(());
+        }
+        self.allocation = Some(size);
+        self.control_stream
+            .write_all(format!("200 Allocated {} bytes.\r\n", size).as_bytes())
+            .await?;
+        Ok(())
+    }
+}
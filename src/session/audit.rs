@@ -0,0 +1,39 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use crate::audit::{Event, Outcome};
+use slog::error;
+use std::path::Path;
+
+impl FTPSession {
+    /// Append an entry to the audit log, if one is configured.
+    pub(super) async fn audit(
+        &self,
+        action: &str,
+        path: Option<&Path>,
+        to: Option<&Path>,
+        outcome: Outcome,
+    ) {
+        let audit_log = match &self.audit_log {
+            Some(audit_log) => audit_log,
+            None => return,
+        };
+        let peer = match self.control_stream.peer_addr() {
+            Ok(remote) => remote.ip().to_string(),
+            Err(_) => String::from("unknown"),
+        };
+        let event = Event {
+            session_id: self.session_id,
+            user: &self.current_user,
+            peer: &peer,
+            action,
+            path,
+            to,
+            outcome,
+        };
+        if let Err(err) = audit_log.record(event).await {
+            error!(self.logger, "Failed to write the audit log: {}", err);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use std::{
+    io,
+    ops::Deref,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+/// The control connection, remembering the code of the last reply written
+/// so that commands can be counted by outcome.
+pub(super) struct ControlStream {
+    stream: TcpStream,
+    reply_code: [u8; 3],
+    /// How far into the current line has been written, up to 4.
+    column: usize,
+}
+
+impl ControlStream {
+    pub(super) fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            reply_code: *b"000",
+            column: 0,
+        }
+    }
+
+    /// The code of the last reply started, or `000` if there was none.
+    pub(super) fn reply_code(&self) -> &str {
+        std::str::from_utf8(&self.reply_code).unwrap_or("000")
+    }
+
+    fn record(&mut self, written: &[u8]) {
+        for &byte in written {
+            if byte == b'\n' {
+                self.column = 0;
+                continue;
+            }
+            if self.column < 3 {
+                if self.column == 0 && !byte.is_ascii_digit() {
+                    // Not a reply line, such as the text of a multi-line reply.
+                    self.column = 4;
+                    continue;
+                }
+                self.reply_code[self.column] = byte;
+            }
+            self.column = (self.column + 1).min(4);
+        }
+    }
+}
+
+impl Deref for ControlStream {
+    type Target = TcpStream;
+
+    fn deref(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl AsyncRead for ControlStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ControlStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(len)) = written {
+            self.record(&buf[..len]);
+        }
+        written
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
@@ -2,7 +2,6 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use super::FTPSession;
-use crate::utils::fs as utfs;
 use std::path::Path;
 use tokio::io::AsyncWriteExt;
 
@@ -13,19 +12,25 @@ impl FTPSession {
     ) -> tokio::io::Result<()> {
         if !self.is_logged_in {
             self.control_stream
-                .write(b"530 Please login with USER and PASS.\r\n")
+                .write_all(b"530 Please login with USER and PASS.\r\n")
                 .await?;
             return Ok(());
         }
-        let path = self.current_path.join(path);
-        if utfs::is_dir(&path).await {
-            self.current_path = path.canonicalize()?;
+        let path = self.resolve_path(path);
+        let is_dir = self
+            .storage
+            .stat(&path)
+            .await
+            .map(|meta| meta.is_dir())
+            .unwrap_or(false);
+        if is_dir {
+            self.current_path = path;
             self.control_stream
-                .write(b"250 Directory successfully changed.\r\n")
+                .write_all(b"250 Directory successfully changed.\r\n")
                 .await?;
         } else {
             self.control_stream
-                .write(b"550 Failed to change directory.\r\n")
+                .write_all(b"550 Failed to change directory.\r\n")
                 .await?;
         }
         Ok(())
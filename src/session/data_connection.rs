@@ -0,0 +1,126 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{FTPSession, TransferMod};
+use crate::utils::config::ActiveMode;
+use slog::{error, warn};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpSocket, TcpStream},
+};
+
+impl FTPSession {
+    /// Open the data connection prepared by PORT or PASV. When it cannot be
+    /// opened, the client is told so and `None` is returned. Either way the
+    /// transfer mode is used up.
+    pub(super) async fn open_data_connection(&mut self) -> tokio::io::Result<Option<TcpStream>> {
+        match std::mem::replace(&mut self.transfer_mode, TransferMod::Disable) {
+            TransferMod::Active(remote) => match self.connect_active(remote).await {
+                Ok(data_stream) => return Ok(Some(data_stream)),
+                Err(err) => {
+                    error!(self.logger, "Failed to connect to {}: {}", remote, err);
+                    self.control_stream
+                        .write_all(b"425 Can't open data connection.\r\n")
+                        .await?;
+                }
+            },
+            TransferMod::Passive(server, lease, deadline) => {
+                match tokio::time::timeout_at(deadline, self.accept_passive(&server)).await {
+                    Ok(Ok(data_stream)) => {
+                        drop((server, lease));
+                        return Ok(Some(data_stream));
+                    }
+                    Err(_) => {
+                        error!(
+                            self.logger,
+                            "No data connection within the passive timeout."
+                        );
+                        self.control_stream
+                            .write_all(b"425 Can't open data connection.\r\n")
+                            .await?;
+                    }
+                    Ok(Err(err)) => {
+                        error!(self.logger, "Unexpected data connection: {}", err);
+                        self.control_stream
+                            .write_all(b"426 Transfer aborted.\r\n")
+                            .await?;
+                    }
+                }
+            }
+            TransferMod::Disable => {
+                self.control_stream
+                    .write_all(b"425 Use PORT or PASV first.\r\n")
+                    .await?;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Accept the client's connection on `server`. Connections from other
+    /// hosts, which may be trying to steal or inject the data, are closed
+    /// and waited past.
+    async fn accept_passive(&self, server: &TcpListener) -> io::Result<TcpStream> {
+        loop {
+            let (data_stream, remote) = server.accept().await?;
+            if !self.config.check_passive_peer || self.data_peer_allowed(remote.ip()) {
+                return Ok(data_stream);
+            }
+            warn!(
+                self.logger,
+                "Rejected data connection from {} on {}",
+                remote,
+                server.local_addr()?
+            );
+        }
+    }
+
+    /// Whether a data connection with `ip` is allowed: only the client
+    /// itself, unless the user may transfer between servers.
+    pub(super) fn data_peer_allowed(&self, ip: IpAddr) -> bool {
+        self.user.allow_fxp
+            || self
+                .control_stream
+                .peer_addr()
+                .is_ok_and(|x| x.ip().to_canonical() == ip.to_canonical())
+    }
+
+    /// Connect to `remote` from the address the client reached the control
+    /// connection on, so that both connections use the same interface.
+    async fn connect_active(&self, remote: SocketAddr) -> io::Result<TcpStream> {
+        let control = self.control_stream.local_addr()?;
+        let ip = match control.ip() {
+            // A dual-stack listener sees IPv4 clients as mapped addresses.
+            IpAddr::V6(ip) if remote.is_ipv4() => {
+                ip.to_ipv4_mapped().map_or(control.ip(), IpAddr::V4)
+            }
+            ip => ip,
+        };
+        let socket = match ip {
+            IpAddr::V4(_) => TcpSocket::new_v4()?,
+            IpAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        let port = match self.config.active_mode {
+            ActiveMode::Ephemeral => 0,
+            ActiveMode::Strict => {
+                // Every session connects from this port, which works as long
+                // as they connect to different remote addresses.
+                socket.set_reuseaddr(true)?;
+                match control.port().checked_sub(1) {
+                    Some(port) if port != 0 => port,
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrNotAvailable,
+                            "no port below the control port",
+                        ))
+                    }
+                }
+            }
+        };
+        socket.bind(SocketAddr::new(ip, port))?;
+        socket.connect(remote).await
+    }
+}
@@ -0,0 +1,156 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{storage::StorageBackend, utils::config::QuotaConfig};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+
+/// How much is stored under the homes of users with a quota, shared by the
+/// sessions of a server. A home is only counted once its user needs it, then
+/// kept up to date as files are written and deleted, by whoever does so.
+///
+/// Homes are paths in the served storage. Paths given to the methods are
+/// those of a session, relative to its own home.
+#[derive(Clone, Default)]
+pub(crate) struct DiskUsage {
+    homes: Arc<std::sync::Mutex<HashMap<PathBuf, Counted>>>,
+}
+
+/// The usage of a home, or `None` until it is counted. Each home has a lock
+/// of its own, so that counting one only holds up the changes to it.
+type Counted = Arc<Mutex<Option<Usage>>>;
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl DiskUsage {
+    /// What is stored under `home`, seen by the session as `storage`.
+    pub(crate) async fn get(&self, storage: &dyn StorageBackend, home: &Path) -> io::Result<Usage> {
+        let counted = self
+            .homes
+            .lock()
+            .unwrap()
+            .entry(home.to_path_buf())
+            .or_default()
+            .clone();
+        let mut usage = counted.lock().await;
+        match *usage {
+            Some(usage) => Ok(usage),
+            None => {
+                let counted = count(storage).await?;
+                *usage = Some(counted);
+                Ok(counted)
+            }
+        }
+    }
+
+    /// Count `bytes` and `files` more as used at `path`, or fewer when
+    /// negative, in every counted home holding it. With a `quota`, the
+    /// session's own home is counted if it is not yet, and growth that would
+    /// take it over the quota is refused with `QuotaExceeded`.
+    pub(crate) async fn add(
+        &self,
+        storage: &dyn StorageBackend,
+        home: &Path,
+        path: &Path,
+        bytes: i64,
+        files: i64,
+        quota: Option<&QuotaConfig>,
+    ) -> io::Result<()> {
+        let path = home.join(path.strip_prefix("/").unwrap_or(path));
+        let update = |usage: &Usage| Usage {
+            bytes: usage.bytes.saturating_add_signed(bytes),
+            files: usage.files.saturating_add_signed(files),
+        };
+        loop {
+            if quota.is_some() {
+                self.get(storage, home).await?;
+            }
+            // Homes are locked in order, so that sessions changing files in
+            // the same homes don't wait on each other.
+            let mut holding: Vec<(PathBuf, Counted)> = self
+                .homes
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(counted, _)| path.starts_with(counted))
+                .map(|(counted, usage)| (counted.clone(), usage.clone()))
+                .collect();
+            holding.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut usages = Vec::with_capacity(holding.len());
+            for (_, usage) in &holding {
+                usages.push(usage.lock().await);
+            }
+            if let Some(quota) = quota {
+                let own = holding.iter().position(|(counted, _)| counted == home);
+                let usage = match own.and_then(|own| *usages[own]) {
+                    Some(usage) => update(&usage),
+                    // Invalidated since it was counted.
+                    None => continue,
+                };
+                let over = |used: u64, limit: Option<u64>, growth: i64| {
+                    growth > 0 && limit.is_some_and(|limit| used > limit)
+                };
+                if over(usage.bytes, quota.bytes, bytes) || over(usage.files, quota.files, files) {
+                    return Err(io::Error::new(io::ErrorKind::QuotaExceeded, OverQuota));
+                }
+            }
+            for usage in usages.iter_mut().filter_map(|usage| usage.as_mut()) {
+                *usage = update(usage);
+            }
+            return Ok(());
+        }
+    }
+
+    /// Count the usage again the next time it is needed, after changes whose
+    /// effect is not known.
+    pub(crate) fn invalidate(&self) {
+        self.homes.lock().unwrap().clear();
+    }
+}
+
+/// Add up the files of the whole storage, as seen by a session.
+async fn count(storage: &dyn StorageBackend) -> io::Result<Usage> {
+    let mut usage = Usage::default();
+    let mut pending = vec![PathBuf::from("/")];
+    while let Some(dir) = pending.pop() {
+        for entry in storage.list(&dir).await? {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            if entry.metadata.is_dir() {
+                pending.push(Path::new(&dir).join(&entry.name));
+            } else {
+                usage.bytes += entry.metadata.size;
+                usage.files += 1;
+            }
+        }
+    }
+    Ok(usage)
+}
+
+/// Whether `err` is a refusal by [`DiskUsage::add`], rather than a backend
+/// running out of space.
+pub(crate) fn is_over_quota(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|x| x.is::<OverQuota>())
+}
+
+#[derive(Debug)]
+struct OverQuota;
+
+impl fmt::Display for OverQuota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("user quota exceeded")
+    }
+}
+
+impl Error for OverQuota {}
@@ -1,18 +1,46 @@
 // Copyright 2021 Slowy <slowyfine@gmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use super::FTPSession;
+use super::{FTPSession, HashAlgorithm};
 use tokio::io::AsyncWriteExt;
 
-pub const FEATURES: &[&str] = &[" PASV\r\n", " UTF8\r\n"];
+/// Features listed by FEAT. HASH is followed by the algorithms, the one in
+/// use marked with a star.
+pub const FEATURES: &[&str] = &[
+    " EPRT\r\n",
+    " HASH\r\n",
+    " PASV\r\n",
+    " RANG STREAM\r\n",
+    " REST STREAM\r\n",
+    " UTF8\r\n",
+    " XCRC\r\n",
+    " XMD5\r\n",
+    " XSHA1\r\n",
+    " XSHA256\r\n",
+];
 
 impl FTPSession {
     pub async fn list_features(&mut self) -> tokio::io::Result<()> {
-        self.control_stream.write(b"221-Features:\r\n").await?;
+        self.control_stream.write_all(b"221-Features:\r\n").await?;
         for &item in FEATURES {
-            self.control_stream.write(item.as_bytes()).await?;
+            if item == " HASH\r\n" {
+                let algorithms: Vec<String> = HashAlgorithm::ALL
+                    .iter()
+                    .map(|&x| {
+                        if x == self.hash_algorithm {
+                            format!("{}*", x.name())
+                        } else {
+                            x.name().to_string()
+                        }
+                    })
+                    .collect();
+                let line = format!(" HASH {}\r\n", algorithms.join(";"));
+                self.control_stream.write_all(line.as_bytes()).await?;
+            } else {
+                self.control_stream.write_all(item.as_bytes()).await?;
+            }
         }
-        self.control_stream.write(b"221 End\r\n").await?;
+        self.control_stream.write_all(b"221 End\r\n").await?;
         Ok(())
     }
 }
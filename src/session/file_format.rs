@@ -8,7 +8,7 @@ impl FTPSession {
     pub async fn set_transfer_mode(&mut self, mode: &str) -> tokio::io::Result<()> {
         let mode = mode.to_ascii_uppercase();
         self.control_stream
-            .write(if mode == "S" {
+            .write_all(if mode == "S" {
                 b"200 Mode set to S.\r\n"
             } else {
                 b"504 Bad MODE command.\r\n"
@@ -0,0 +1,42 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use crate::audit::Outcome;
+use slog::warn;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+impl FTPSession {
+    /// Check the name of `path`, about to be created by `action`, against the
+    /// user's upload filter. Refused names are replied to with 553.
+    pub(super) async fn name_allowed(
+        &mut self,
+        action: &str,
+        path: &Path,
+        from: Option<&Path>,
+    ) -> tokio::io::Result<bool> {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return Ok(true),
+        };
+        let refused = match &self.upload_filter {
+            Some(filter) => filter.check(&name).err(),
+            None => Some("no valid upload filter"),
+        };
+        let reason = match refused {
+            Some(reason) => reason,
+            None => return Ok(true),
+        };
+        warn!(self.logger, "Refused to {} {:?}: {}", action, path, reason);
+        let outcome = Outcome::Denied(reason.to_string());
+        match from {
+            Some(from) => self.audit(action, Some(from), Some(path), outcome).await,
+            None => self.audit(action, Some(path), None, outcome).await,
+        }
+        self.control_stream
+            .write_all(format!("553 File name not allowed: {}.\r\n", reason).as_bytes())
+            .await?;
+        Ok(false)
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use crate::storage::ReadStream;
+use sha2::digest::DynDigest;
+use slog::error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Checksums that HASH and the X commands can compute.
+#[derive(Clone, Copy, PartialEq)]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+    Md5,
+    Crc32,
+}
+
+impl HashAlgorithm {
+    /// All algorithms, in the order listed by FEAT.
+    pub const ALL: [HashAlgorithm; 5] = [
+        HashAlgorithm::Sha1,
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Sha512,
+        HashAlgorithm::Md5,
+        HashAlgorithm::Crc32,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "SHA-1",
+            HashAlgorithm::Sha256 => "SHA-256",
+            HashAlgorithm::Sha512 => "SHA-512",
+            HashAlgorithm::Md5 => "MD5",
+            HashAlgorithm::Crc32 => "CRC32",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|x| x.name().eq_ignore_ascii_case(name))
+    }
+
+    fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha1 => Hasher::Digest(Box::<sha1::Sha1>::default()),
+            HashAlgorithm::Sha256 => Hasher::Digest(Box::<sha2::Sha256>::default()),
+            HashAlgorithm::Sha512 => Hasher::Digest(Box::<sha2::Sha512>::default()),
+            HashAlgorithm::Md5 => Hasher::Digest(Box::<md5::Md5>::default()),
+            HashAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+}
+
+enum Hasher {
+    Digest(Box<dyn DynDigest + Send>),
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Digest(digest) => digest.update(data),
+            Hasher::Crc32(crc) => crc.update(data),
+        }
+    }
+
+    fn finish(self) -> String {
+        match self {
+            Hasher::Digest(digest) => hex::encode(digest.finalize()),
+            Hasher::Crc32(crc) => format!("{:08x}", crc.finalize()),
+        }
+    }
+}
+
+/// Split the argument of an X command into the path and the optional start
+/// and end offsets following it. A path ending in a space and a number has to
+/// be quoted.
+fn legacy_arguments(para: &str) -> Option<(&str, Option<u64>, Option<u64>)> {
+    if let Some(quoted) = para.strip_prefix('"') {
+        let (path, rest) = quoted.split_once('"')?;
+        let mut numbers = rest.split_whitespace();
+        let start = numbers.next().map(str::parse).transpose().ok()?;
+        let end = numbers.next().map(str::parse).transpose().ok()?;
+        if numbers.next().is_some() {
+            return None;
+        }
+        return Some((path, start, end));
+    }
+    let mut path = para;
+    let mut numbers = Vec::new();
+    while numbers.len() < 2 {
+        match path.rsplit_once(' ') {
+            Some((rest, number)) if !rest.is_empty() => match number.parse::<u64>() {
+                Ok(number) => {
+                    numbers.insert(0, number);
+                    path = rest;
+                }
+                Err(_) => break,
+            },
+            _ => break,
+        }
+    }
+    Some((path, numbers.first().copied(), numbers.get(1).copied()))
+}
+
+impl FTPSession {
+    /// Choose the algorithm for HASH, or show the one chosen.
+    pub(super) async fn set_hash_algorithm(&mut self, name: &str) -> tokio::io::Result<()> {
+        let name = name.trim();
+        if !name.is_empty() {
+            match HashAlgorithm::parse(name) {
+                Some(algorithm) => self.hash_algorithm = algorithm,
+                None => {
+                    self.control_stream
+                        .write_all(b"504 Unknown hash algorithm.\r\n")
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        self.control_stream
+            .write_all(format!("200 {}\r\n", self.hash_algorithm.name()).as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// Limit the next HASH to the bytes from `start` to `end`, both included.
+    /// `RANG 1 0` goes back to the whole file.
+    pub async fn set_range(&mut self, para: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        let range = para
+            .split_once(' ')
+            .and_then(|(start, end)| Some((start.parse::<u64>().ok()?, end.parse::<u64>().ok()?)));
+        match range {
+            Some((1, 0)) => {
+                self.hash_range = None;
+                self.control_stream
+                    .write_all(b"350 Restarting at 0. Ending at end of file.\r\n")
+                    .await?;
+            }
+            Some((start, end)) if start <= end => {
+                self.hash_range = Some((start, end));
+                self.control_stream
+                    .write_all(
+                        format!("350 Restarting at {}. Ending at {}.\r\n", start, end).as_bytes(),
+                    )
+                    .await?;
+            }
+            _ => {
+                self.control_stream
+                    .write_all(b"501 Invalid range.\r\n")
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checksum a file with the algorithm chosen by OPTS HASH, over the range
+    /// set by RANG if any. The reply gives the range hashed as the offset of
+    /// its first byte and the offset just past its last.
+    pub async fn hash(&mut self, path: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        let range = self.hash_range.take();
+        let algorithm = self.hash_algorithm;
+        let (start, end) = match range {
+            Some((start, end)) => (Some(start), end.checked_add(1)),
+            None => (None, None),
+        };
+        if let Some((start, end, checksum)) = self.checksum(path, algorithm, start, end).await? {
+            let reply = format!(
+                "213 {} {}-{} {} {}\r\n",
+                algorithm.name(),
+                start,
+                end,
+                checksum,
+                path
+            );
+            self.control_stream.write_all(reply.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// XCRC, XMD5, XSHA1 and XSHA256: checksum a file, or the part of it from
+    /// the start offset up to the end offset when given.
+    pub async fn legacy_hash(
+        &mut self,
+        algorithm: HashAlgorithm,
+        para: &str,
+    ) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        let (path, start, end) = match legacy_arguments(para) {
+            Some(arguments) => arguments,
+            None => {
+                self.control_stream
+                    .write_all(b"501 Invalid arguments.\r\n")
+                    .await?;
+                return Ok(());
+            }
+        };
+        if let Some((_, _, checksum)) = self.checksum(path, algorithm, start, end).await? {
+            self.control_stream
+                .write_all(format!("250 {}\r\n", checksum).as_bytes())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Hash the bytes of `path` from `start` up to, not including, `end`,
+    /// returning the range actually hashed with the checksum. Failures are
+    /// replied to, giving `None`.
+    async fn checksum(
+        &mut self,
+        path: &str,
+        algorithm: HashAlgorithm,
+        start: Option<u64>,
+        end: Option<u64>,
+    ) -> tokio::io::Result<Option<(u64, u64, String)>> {
+        let path = self.resolve_path(path);
+        let size = match self.storage.stat(&path).await {
+            Ok(metadata) if !metadata.is_dir() => metadata.size,
+            _ => {
+                self.control_stream
+                    .write_all(b"550 Failed to open file.\r\n")
+                    .await?;
+                return Ok(None);
+            }
+        };
+        let start = start.unwrap_or(0);
+        let end = end.map_or(size, |end| end.min(size));
+        if start > end {
+            self.control_stream
+                .write_all(b"501 Range is past the end of the file.\r\n")
+                .await?;
+            return Ok(None);
+        }
+        let mut file = match self.storage.open_read(&path, start).await {
+            Ok(file) => file,
+            Err(_) => {
+                self.control_stream
+                    .write_all(b"550 Failed to open file.\r\n")
+                    .await?;
+                return Ok(None);
+            }
+        };
+        match hash_stream(&mut file, end - start, algorithm.hasher()).await {
+            Ok(checksum) => Ok(Some((start, end, checksum))),
+            Err(err) => {
+                error!(self.logger, "Error while hashing {:?}: {}", path, err);
+                self.control_stream
+                    .write_all(b"451 Failed to read file.\r\n")
+                    .await?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Feed the next `len` bytes of `file` to `hasher`.
+async fn hash_stream(
+    file: &mut ReadStream,
+    len: u64,
+    mut hasher: Hasher,
+) -> std::io::Result<String> {
+    let mut buffer = [0; 32768];
+    let mut file = file.take(len);
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finish())
+}
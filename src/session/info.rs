@@ -6,7 +6,9 @@ use tokio::io::AsyncWriteExt;
 
 impl FTPSession {
     pub async fn print_info(&mut self) -> tokio::io::Result<()> {
-        self.control_stream.write(b"215 UNIX Type: L8\r\n").await?;
+        self.control_stream
+            .write_all(b"215 UNIX Type: L8\r\n")
+            .await?;
         Ok(())
     }
 }
@@ -2,17 +2,24 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use super::FTPSession;
+use crate::{
+    audit::Outcome,
+    storage::Chroot,
+    utils::{filter::NameFilter, fs as utfs},
+};
+use slog::{error, info, o, warn};
+use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 
 impl FTPSession {
     pub async fn pre_login(&mut self, username: &str) -> tokio::io::Result<()> {
         if self.is_logged_in {
             self.control_stream
-                .write(b"530 Can't change to another user.\r\n")
+                .write_all(b"530 Can't change to another user.\r\n")
                 .await?;
         } else {
             self.control_stream
-                .write(b"331 Please specify the password.\r\n")
+                .write_all(b"331 Please specify the password.\r\n")
                 .await?;
             self.current_user = String::from(username);
         }
@@ -22,24 +29,73 @@ impl FTPSession {
     pub async fn try_login(&mut self, password: &str) -> tokio::io::Result<()> {
         if self.is_logged_in {
             self.control_stream
-                .write(b"230 Already logged in.\r\n")
+                .write_all(b"230 Already logged in.\r\n")
                 .await?;
         } else if self.current_user == "anonymous" {
             self.control_stream
-                .write(b"230 Login successfully.\r\n")
+                .write_all(b"230 Login successfully.\r\n")
                 .await?;
             self.is_anonymous = true;
-            self.is_logged_in = true;
+            self.logged_in().await;
         } else if self.current_user == self.config.username && password == self.config.password {
             self.control_stream
-                .write(b"230 Login successful.\r\n")
+                .write_all(b"230 Login successful.\r\n")
                 .await?;
-            self.is_logged_in = true;
+            self.logged_in().await;
         } else {
+            warn!(self.logger, "Login failed for {}.", self.current_user);
+            self.metrics.login(false);
+            let reason = if self.current_user == self.config.username {
+                "wrong password"
+            } else {
+                "unknown user"
+            };
+            self.audit("login", None, None, Outcome::Failure(reason.into()))
+                .await;
             self.control_stream
-                .write(b"530 Login incorrect.\r\n")
+                .write_all(b"530 Login incorrect.\r\n")
                 .await?;
         }
         Ok(())
     }
+
+    /// Mark the session logged in and tag its log records with the user.
+    async fn logged_in(&mut self) {
+        self.is_logged_in = true;
+        self.user = self.config.user(&self.current_user);
+        self.metrics.login(true);
+        self.logger = Arc::new(self.logger.new(o!("user" => self.current_user.clone())));
+        if let Some(home) = &self.user.home {
+            self.home = utfs::resolve("/", home);
+            // Create the home and its parents where missing.
+            let mut missing: Vec<_> = self.home.ancestors().collect();
+            missing.reverse();
+            for dir in missing {
+                if self.storage.stat(dir).await.is_ok() {
+                    continue;
+                }
+                if let Err(err) = self.storage.mkdir(dir).await {
+                    error!(self.logger, "Failed to create home {:?}: {}", dir, err);
+                    break;
+                }
+            }
+            self.storage = Arc::new(Chroot::new(self.storage.clone(), &self.home));
+        }
+        let filter = match &self.user.upload_filter {
+            Some(filter) => filter,
+            None => &self.config.upload_filter,
+        };
+        self.upload_filter = match NameFilter::new(filter) {
+            Ok(filter) => Some(filter),
+            Err(err) => {
+                error!(
+                    self.logger,
+                    "Invalid upload filter, refusing uploads: {}", err
+                );
+                None
+            }
+        };
+        info!(self.logger, "Logged in.");
+        self.audit("login", None, None, Outcome::Success).await;
+    }
 }
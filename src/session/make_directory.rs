@@ -0,0 +1,41 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use crate::audit::Outcome;
+use slog::debug;
+use tokio::io::AsyncWriteExt;
+
+impl FTPSession {
+    pub async fn make_directory(&mut self, path: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        let path = self.resolve_path(path);
+        if !self.name_allowed("mkdir", &path, None).await? {
+            return Ok(());
+        }
+        let result = self.storage.mkdir(&path).await;
+        self.audit("mkdir", Some(&path), None, Outcome::from(&result))
+            .await;
+        match result {
+            Ok(_) => {
+                self.control_stream
+                    .write_all(
+                        format!("257 \"{}\" created.\r\n", path.to_string_lossy()).as_bytes(),
+                    )
+                    .await?;
+            }
+            Err(err) => {
+                debug!(self.logger, "Failed to create {:?}: {}", path, err);
+                self.control_stream
+                    .write_all(b"550 Create directory operation failed.\r\n")
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
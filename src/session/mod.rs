@@ -1,43 +1,71 @@
 // Copyright 2021 Slowy <slowyfine@gmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
+mod allocate;
+mod audit;
+mod control;
 mod cwd;
+mod data_connection;
+mod disk_usage;
 mod features;
 mod file_format;
 mod file_struct;
+mod filter;
+mod hash;
 mod info;
 mod list;
 mod login;
+mod make_directory;
+mod notify;
+mod options;
+mod passive_ports;
 mod pwd;
 mod quit;
 mod receive;
+mod remove;
+mod rename;
+mod restart;
 mod send;
+mod service_closing;
+mod site;
 mod transfer_mode;
 mod transfer_type;
 mod unicode;
 mod unknown_command;
 mod wait;
 mod welcome;
+mod xferlog;
 
-use crate::utils::config::Config;
+use control::ControlStream;
+pub(crate) use disk_usage::DiskUsage;
+use hash::HashAlgorithm;
+use passive_ports::Lease;
+pub(crate) use passive_ports::PassivePorts;
+
+use crate::{
+    audit::AuditLog,
+    hooks::Hooks,
+    metrics::Metrics,
+    storage::StorageBackend,
+    utils::{
+        config::{Config, UserConfig},
+        filter::NameFilter,
+        fs as utfs,
+    },
+};
 use slog::{debug, warn, Logger};
-use std::{collections::VecDeque, net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
+    sync::watch,
+    time::Instant,
 };
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn get_session_size() {
-        println!(
-            "FTP Session Size: {}",
-            std::mem::size_of::<super::FTPSession>()
-        )
-    }
-}
-
 enum TransferType {
     Ascii,
     Binary,
@@ -45,81 +73,209 @@ enum TransferType {
 
 enum TransferMod {
     Active(SocketAddr),
-    Passive(TcpListener),
+    /// Listening for the data connection until the deadline.
+    Passive(TcpListener, Lease, Instant),
     Disable,
 }
 
+/// Resolve once shutdown is requested, or never if the server went away or
+/// there is no signal.
+async fn shutdown_requested(signal: &mut Option<watch::Receiver<bool>>) {
+    let signal = match signal {
+        Some(signal) => signal,
+        None => std::future::pending().await,
+    };
+    while !*signal.borrow() {
+        if signal.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Resolve at `deadline`, or never if there is none.
+async fn expired(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The command as it may appear in logs, with the password of PASS hidden.
+fn loggable(command: &[u8]) -> std::borrow::Cow<'_, str> {
+    match command.get(..5) {
+        Some(verb) if verb.eq_ignore_ascii_case(b"PASS ") => "PASS ****".into(),
+        _ => String::from_utf8_lossy(command),
+    }
+}
+
+/// Longest command line accepted on the control connection, CRLF included.
+const MAX_COMMAND_LENGTH: usize = 1024;
+
+/// What was read from the control connection but not handled yet. It lives
+/// across calls to `read_command`, so that dropping one between reads loses
+/// nothing.
+struct Input {
+    pending: Vec<u8>,
+    /// Whether the start of the line in `pending` was thrown away for being
+    /// too long.
+    overlong: bool,
+}
+
+enum Line {
+    Command(Vec<u8>),
+    Overlong,
+    Closed,
+}
+
 pub struct FTPSession {
-    control_stream: TcpStream,
+    control_stream: ControlStream,
     current_user: String,
     is_logged_in: bool,
     is_anonymous: bool,
+    /// Settings for the logged in user.
+    user: UserConfig,
+    /// Where the user's `/` is in the served storage.
+    home: PathBuf,
+    /// What names the logged in user may create.
+    upload_filter: Option<NameFilter>,
     transfer_mode: TransferMod,
     transfer_type: TransferType,
     current_path: PathBuf,
+    rename_source: Option<PathBuf>,
+    /// Where the next RETR or STOR starts, as set by REST.
+    restart_offset: u64,
+    /// Size declared by ALLO for the next STOR or APPE.
+    allocation: Option<u64>,
+    /// Checksum computed by HASH, as chosen with OPTS HASH.
+    hash_algorithm: HashAlgorithm,
+    /// Bytes the next HASH covers, as set by RANG.
+    hash_range: Option<(u64, u64)>,
+    storage: Arc<dyn StorageBackend>,
+    shutdown_signal: Option<watch::Receiver<bool>>,
+    metrics: Arc<Metrics>,
+    passive_ports: PassivePorts,
+    disk_usage: DiskUsage,
+    hooks: Hooks,
+    audit_log: Option<Arc<AuditLog>>,
+    session_id: u64,
     pub logger: Arc<Logger>,
     pub config: Arc<Config>,
 }
 
 impl FTPSession {
-    pub fn new(control_stream: TcpStream, logger: Arc<Logger>, config: Arc<Config>) -> Self {
+    pub fn new(
+        control_stream: TcpStream,
+        logger: Arc<Logger>,
+        config: Arc<Config>,
+        storage: Arc<dyn StorageBackend>,
+    ) -> Self {
         Self {
-            control_stream,
+            control_stream: ControlStream::new(control_stream),
             current_user: String::new(),
             is_logged_in: false,
             is_anonymous: false,
+            user: UserConfig::default(),
+            home: PathBuf::from("/"),
+            upload_filter: None,
             transfer_mode: TransferMod::Disable,
             transfer_type: TransferType::Ascii,
-            current_path: config.path.clone(),
+            current_path: PathBuf::from("/"),
+            rename_source: None,
+            restart_offset: 0,
+            allocation: None,
+            hash_algorithm: HashAlgorithm::Sha256,
+            hash_range: None,
+            storage,
+            shutdown_signal: None,
+            metrics: Arc::default(),
+            passive_ports: PassivePorts::default(),
+            disk_usage: DiskUsage::default(),
+            hooks: Hooks::new(config.hooks.max_running, config.hooks.max_queued),
+            audit_log: None,
+            session_id: 0,
             logger,
             config,
         }
     }
 
+    /// Close the session with 421 once `signal` turns true and the session is
+    /// not in the middle of a command.
+    pub(crate) fn set_shutdown_signal(&mut self, signal: watch::Receiver<bool>) {
+        self.shutdown_signal = Some(signal);
+    }
+
+    /// Count what this session does in `metrics` rather than in a private set.
+    pub(crate) fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = metrics;
+    }
+
+    /// Take passive mode ports from `passive_ports`, shared with other
+    /// sessions.
+    pub(crate) fn set_passive_ports(&mut self, passive_ports: PassivePorts) {
+        self.passive_ports = passive_ports;
+    }
+
+    /// Keep track of storage usage in `disk_usage`, shared with the other
+    /// sessions on the same storage.
+    pub(crate) fn set_disk_usage(&mut self, disk_usage: DiskUsage) {
+        self.disk_usage = disk_usage;
+    }
+
+    /// Run hooks through `hooks`, shared with other sessions so that the
+    /// limit on those running holds across them.
+    pub(crate) fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = hooks;
+    }
+
+    /// Record logins and changes to files in `audit_log`, as done by the
+    /// session numbered `session_id`.
+    pub(crate) fn set_audit_log(&mut self, audit_log: Arc<AuditLog>, session_id: u64) {
+        self.audit_log = Some(audit_log);
+        self.session_id = session_id;
+    }
+
     pub async fn run(&mut self) -> tokio::io::Result<()> {
+        let _open = self.metrics.session();
         self.welcome().await?;
-        let mut buffer = [0; 1024];
-        let mut byte_stream = VecDeque::with_capacity(1024);
-        let mut command = Vec::with_capacity(1024);
+        let mut input = Input {
+            pending: Vec::with_capacity(MAX_COMMAND_LENGTH),
+            overlong: false,
+        };
+        let mut shutdown_signal = self.shutdown_signal.take();
         loop {
-            let mut pre = 0;
-            command.clear();
-            loop {
-                while !byte_stream.is_empty() {
-                    let &cur = byte_stream.front().unwrap();
-                    byte_stream.pop_front();
-                    command.push(cur);
-                    if (pre, cur) == (b'\r', b'\n') {
-                        break;
-                    }
-                    pre = cur;
+            let passive_deadline = match &self.transfer_mode {
+                TransferMod::Passive(_, _, deadline) => Some(*deadline),
+                _ => None,
+            };
+            let line = tokio::select! {
+                biased;
+                _ = shutdown_requested(&mut shutdown_signal) => {
+                    self.transfer_mode = TransferMod::Disable;
+                    return self.service_closing().await;
                 }
-                if command.ends_with(&[b'\r', b'\n']) || command.len() > 1024 {
-                    break;
+                _ = expired(passive_deadline) => {
+                    debug!(self.logger, "Closing passive port, no transfer was asked for.");
+                    self.transfer_mode = TransferMod::Disable;
+                    continue;
                 }
-                let len = self.control_stream.read(&mut buffer).await?;
-                if len == 0 {
-                    return Ok(());
+                line = self.read_command(&mut input) => line?,
+            };
+            let mut command = match line {
+                Line::Command(command) => command,
+                Line::Overlong => {
+                    warn!(self.logger, "Unknown command received.");
+                    self.unknown_command().await?;
+                    self.control_stream.flush().await?;
+                    self.metrics.command("", self.control_stream.reply_code());
+                    continue;
                 }
-                for &cur in buffer[0..len].iter() {
-                    byte_stream.push_back(cur);
-                }
-            }
-            if !command.ends_with(&[b'\r', b'\n']) {
-                warn!(self.logger, "Unknown command received.");
-                self.unknown_command().await?;
-                continue;
-            }
-            command.pop();
-            command.pop();
+                Line::Closed => return Ok(()),
+            };
             let len = command.len();
-            debug!(
-                self.logger,
-                "Receive command: {}",
-                String::from_utf8_lossy(&command)
-            );
+            debug!(self.logger, "Receive command: {}", loggable(&command));
             command[0..std::cmp::min(len, 4)].make_ascii_uppercase();
             let command = String::from_utf8_lossy(&command);
+            let verb = command.split(' ').next().unwrap_or_default();
             match command.as_bytes() {
                 b"PASV" => self.set_passive().await?,
                 b"PWD" => self.print_working_directory().await?,
@@ -128,22 +284,99 @@ impl FTPSession {
                 b"NOOP" => self.wait().await?,
                 b"LIST" => self.list("").await?,
                 b"OPTS UTF8 ON" => self.unicode().await?,
-                b"QUIT" => return self.quit().await,
+                b"QUIT" => {
+                    let result = self.quit().await;
+                    self.metrics.command(verb, self.control_stream.reply_code());
+                    return result;
+                }
                 _ => match command.split_once(' ') {
                     Some(("USER", para)) => self.pre_login(para).await?,
                     Some(("PASS", para)) => self.try_login(para).await?,
                     Some(("PORT", para)) => self.set_active(para).await?,
+                    Some(("EPRT", para)) => self.set_extended_active(para).await?,
                     Some(("TYPE", para)) => self.set_transfer_type(para).await?,
                     Some(("MODE", para)) => self.set_transfer_mode(para).await?,
                     Some(("STRU", para)) => self.set_file_struct(para).await?,
                     Some(("CWD", para)) => self.change_working_directory(para).await?,
                     Some(("LIST", para)) => self.list(para).await?,
+                    Some(("REST", para)) => self.restart(para).await?,
                     Some(("RETR", para)) => self.send(para).await?,
                     Some(("STOR", para)) => self.receive(para).await?,
+                    Some(("APPE", para)) => self.append(para).await?,
+                    Some(("ALLO", para)) => self.allocate(para).await?,
+                    Some(("MKD", para)) => self.make_directory(para).await?,
+                    Some(("RMD", para)) => self.remove_directory(para).await?,
+                    Some(("DELE", para)) => self.delete_file(para).await?,
+                    Some(("RNFR", para)) => self.rename_from(para).await?,
+                    Some(("RNTO", para)) => self.rename_to(para).await?,
+                    Some(("SITE", para)) => self.site(para).await?,
+                    Some(("OPTS", para)) => self.options(para).await?,
+                    Some(("HASH", para)) => self.hash(para).await?,
+                    Some(("RANG", para)) => self.set_range(para).await?,
+                    Some(("XCRC", para)) => self.legacy_hash(HashAlgorithm::Crc32, para).await?,
+                    Some(("XMD5", para)) => self.legacy_hash(HashAlgorithm::Md5, para).await?,
+                    Some(("XSHA1", para)) => self.legacy_hash(HashAlgorithm::Sha1, para).await?,
+                    Some(("XSHA256", para)) => {
+                        self.legacy_hash(HashAlgorithm::Sha256, para).await?
+                    }
                     _ => self.unknown_command().await?,
                 },
             }
             self.control_stream.flush().await?;
+            self.metrics.command(verb, self.control_stream.reply_code());
         }
     }
+
+    /// Largest file the logged in user may upload.
+    fn max_upload_size(&self) -> Option<u64> {
+        self.user.max_upload_size.or(self.config.max_upload_size)
+    }
+
+    /// Turn a path given by the client into a virtual path for storage.
+    fn resolve_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        utfs::resolve(&self.current_path, path)
+    }
+
+    /// Take the next CRLF terminated line out of `input`, reading more from
+    /// the control connection when needed. Bytes following the line are kept
+    /// in `input` so that pipelined commands are handled in order.
+    async fn read_command(&mut self, input: &mut Input) -> tokio::io::Result<Line> {
+        let mut buffer = [0; 1024];
+        let pending = &mut input.pending;
+        loop {
+            if let Some(end) = pending.windows(2).position(|x| x == b"\r\n") {
+                let mut command: Vec<u8> = pending.drain(..end + 2).collect();
+                command.truncate(end);
+                let overlong = std::mem::take(&mut input.overlong);
+                return Ok(if overlong || end + 2 > MAX_COMMAND_LENGTH {
+                    Line::Overlong
+                } else {
+                    Line::Command(command)
+                });
+            }
+            if pending.len() > MAX_COMMAND_LENGTH {
+                // Discard the rest of the line, but keep a trailing CR in case
+                // the LF arrives with the next read.
+                let keep = usize::from(pending.ends_with(b"\r"));
+                pending.drain(..pending.len() - keep);
+                input.overlong = true;
+            }
+            let len = self.control_stream.read(&mut buffer).await?;
+            if len == 0 {
+                return Ok(Line::Closed);
+            }
+            pending.extend_from_slice(&buffer[..len]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn get_session_size() {
+        println!(
+            "FTP Session Size: {}",
+            std::mem::size_of::<super::FTPSession>()
+        )
+    }
 }
@@ -0,0 +1,41 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use crate::{hooks::Event, utils::config::HookEvent};
+use std::path::Path;
+
+impl FTPSession {
+    /// Start the configured hooks for a change to `path`. The size is looked
+    /// up when not given.
+    pub(super) async fn notify(
+        &self,
+        kind: HookEvent,
+        path: &Path,
+        from: Option<&Path>,
+        size: Option<u64>,
+    ) {
+        if self.config.hooks.run.is_empty() {
+            return;
+        }
+        let size = match size {
+            Some(size) => Some(size),
+            None => self.storage.stat(path).await.ok().map(|x| x.size),
+        };
+        let peer = match self.control_stream.peer_addr() {
+            Ok(remote) => remote.ip().to_string(),
+            Err(_) => String::from("unknown"),
+        };
+        let event = Event {
+            kind,
+            path: path.to_path_buf(),
+            from: from.map(Path::to_path_buf),
+            real_path: self.storage.local_path(path),
+            real_from: from.and_then(|from| self.storage.local_path(from)),
+            user: self.current_user.clone(),
+            peer,
+            size,
+        };
+        self.hooks.run(&self.config.hooks, &event, &self.logger);
+    }
+}
@@ -0,0 +1,18 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use tokio::io::AsyncWriteExt;
+
+impl FTPSession {
+    pub async fn options(&mut self, para: &str) -> tokio::io::Result<()> {
+        let (option, value) = para.split_once(' ').unwrap_or((para, ""));
+        if option.eq_ignore_ascii_case("HASH") {
+            return self.set_hash_algorithm(value).await;
+        }
+        self.control_stream
+            .write_all(b"501 Option not understood.\r\n")
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::{
+    metrics::{InUse, Metrics},
+    utils::config::PortRange,
+};
+use std::{
+    collections::HashSet,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+use tokio::net::TcpListener;
+
+/// Hands out passive mode ports to the sessions of a server, keeping count
+/// of those open so that a cap and a port range can be honoured.
+#[derive(Clone, Default)]
+pub(crate) struct PassivePorts {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    open: usize,
+    /// Ports of the configured range that are handed out.
+    taken: HashSet<u16>,
+    /// Where to start looking in the range, so that ports are used in turn
+    /// rather than the first ones over and over.
+    next: u16,
+}
+
+/// A passive mode port, given back when dropped.
+pub(crate) struct Lease {
+    state: Arc<Mutex<State>>,
+    port: Option<u16>,
+    _in_use: InUse,
+}
+
+impl PassivePorts {
+    /// Listen on `ip`, on a port from `range` if given, or any free port.
+    /// Fails with `AddrInUse` when `max` ports are open already or no port of
+    /// the range is free.
+    pub(crate) fn open(
+        &self,
+        ip: IpAddr,
+        range: Option<&PortRange>,
+        max: Option<usize>,
+        metrics: &Metrics,
+    ) -> io::Result<(TcpListener, Lease)> {
+        let mut state = self.state.lock().unwrap();
+        if max.is_some_and(|max| state.open >= max) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "too many passive ports open",
+            ));
+        }
+        let (listener, port) = match range {
+            Some(range) => {
+                let (listener, port) = state.bind_in(ip, range)?;
+                (listener, Some(port))
+            }
+            None => (bind(SocketAddr::new(ip, 0))?, None),
+        };
+        state.open += 1;
+        Ok((
+            listener,
+            Lease {
+                state: self.state.clone(),
+                port,
+                _in_use: metrics.passive_port(),
+            },
+        ))
+    }
+}
+
+impl State {
+    fn bind_in(&mut self, ip: IpAddr, range: &PortRange) -> io::Result<(TcpListener, u16)> {
+        if range.min == 0 || range.min > range.max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid passive port range",
+            ));
+        }
+        let len = u32::from(range.max - range.min) + 1;
+        for offset in 0..len {
+            let port = range.min + ((u32::from(self.next) + offset) % len) as u16;
+            if self.taken.contains(&port) {
+                continue;
+            }
+            // Ports held by other programs are skipped too.
+            if let Ok(listener) = bind(SocketAddr::new(ip, port)) {
+                self.taken.insert(port);
+                self.next = ((u32::from(port - range.min) + 1) % len) as u16;
+                return Ok((listener, port));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "no free port in the passive port range",
+        ))
+    }
+}
+
+fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.open -= 1;
+        if let Some(port) = self.port {
+            state.taken.remove(&port);
+        }
+    }
+}
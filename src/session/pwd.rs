@@ -8,12 +8,12 @@ impl FTPSession {
     pub async fn print_working_directory(&mut self) -> tokio::io::Result<()> {
         if !self.is_logged_in {
             self.control_stream
-                .write(b"530 Please login with USER and PASS.\r\n")
+                .write_all(b"530 Please login with USER and PASS.\r\n")
                 .await?;
             return Ok(());
         }
         self.control_stream
-            .write(
+            .write_all(
                 format!(
                     "257 \"{}\" is the current directory.\r\n",
                     self.current_path.to_string_lossy()
@@ -1,130 +1,396 @@
 // Copyright 2021 Slowy <slowyfine@gmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use super::{FTPSession, TransferMod, TransferType};
+use super::{disk_usage::is_over_quota, FTPSession, TransferMod, TransferType};
+use crate::{audit::Outcome, metrics::Direction, storage::WriteStream, utils::config::HookEvent};
 use slog::error;
-use std::{io::BufRead, net::SocketAddr};
+use std::{
+    error::Error,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
 use tokio::{
-    fs::OpenOptions,
     io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpSocket, TcpStream},
+    net::TcpStream,
 };
 
 impl FTPSession {
     pub async fn receive(&mut self, path: &str) -> tokio::io::Result<()> {
+        self.receive_file(path, false).await
+    }
+
+    pub async fn append(&mut self, path: &str) -> tokio::io::Result<()> {
+        self.receive_file(path, true).await
+    }
+
+    /// Store what the client sends as `path`, replacing the file or, with
+    /// `append`, adding to its end.
+    async fn receive_file(&mut self, path: &str, append: bool) -> tokio::io::Result<()> {
         if !self.is_logged_in {
             self.control_stream
-                .write(b"530 Please login with USER and PASS.\r\n")
+                .write_all(b"530 Please login with USER and PASS.\r\n")
                 .await?;
             return Ok(());
         }
-        match &self.transfer_mode {
-            TransferMod::Active(remote) => {
-                let (ip, port) = (self.config.listen, self.config.port - 1);
-                let local = TcpSocket::new_v4()?;
-                local.set_reuseaddr(true)?;
-                match local.bind(SocketAddr::new(ip, port)) {
-                    Ok(_) => match local.connect(*remote).await {
-                        Ok(mut data_stream) => {
-                            self.control_stream
-                                .write(b"150 Ok to send data.\r\n")
-                                .await?;
-                            if let Err(err) = self.receive_inner(path, &mut data_stream).await {
-                                error!(self.logger, "Error during receive: {}", err);
-                                self.control_stream
-                                    .write(b"426 Transfer aborted.\r\n")
-                                    .await?;
-                            } else {
-                                self.control_stream
-                                    .write(b"226 Transfer complete.\r\n")
-                                    .await?;
-                            }
-                        }
-                        Err(err) => {
-                            error!(self.logger, "Failed to connect to remote: {}", err);
-                            self.control_stream
-                                .write(b"425 Can't open data connection.\r\n")
-                                .await?;
-                        }
-                    },
-                    Err(err) => {
-                        error!(self.logger, "Failed to listening port {}: {}", port, err);
-                        self.control_stream
-                            .write(b"425 Server data connection close.\r\n")
-                            .await?;
-                    }
-                }
+        let path = self.resolve_path(path);
+        if !self.name_allowed("upload", &path, None).await? {
+            self.transfer_mode = TransferMod::Disable;
+            return Ok(());
+        }
+        let existing = match self.storage.stat(&path).await {
+            Ok(meta) if !meta.is_dir() => Some(meta.size),
+            _ => None,
+        };
+        let restart = std::mem::take(&mut self.restart_offset);
+        let allocation = self.allocation.take();
+        if !append && restart > existing.unwrap_or(0) {
+            self.control_stream
+                .write_all(b"554 Restart offset is past the end of the file.\r\n")
+                .await?;
+            self.transfer_mode = TransferMod::Disable;
+            return Ok(());
+        }
+        let offset = if append {
+            existing.unwrap_or(0)
+        } else {
+            restart
+        };
+        let max_size = self.max_upload_size();
+        if let (Some(max), Some(size)) = (max_size, allocation) {
+            if offset.saturating_add(size) > max {
+                error!(self.logger, "Upload of {:?} refused: {}", path, TooLarge);
+                let outcome = Outcome::Failure(TooLarge.to_string());
+                self.audit("upload", Some(&path), None, outcome).await;
+                self.control_stream
+                    .write_all(b"552 File exceeds the maximum upload size.\r\n")
+                    .await?;
+                self.transfer_mode = TransferMod::Disable;
+                return Ok(());
             }
-            TransferMod::Passive(server) => match server.accept().await {
-                Ok((mut data_stream, _)) => {
-                    self.control_stream
-                        .write(b"150 Ok to send data.\r\n")
-                        .await?;
-                    if let Err(err) = self.receive_inner(path, &mut data_stream).await {
-                        error!(self.logger, "Error during receive: {}", err);
-                        self.control_stream
-                            .write(b"426 Transfer aborted.\r\n")
-                            .await?;
-                    } else {
-                        self.control_stream
-                            .write(b"226 Transfer complete.\r\n")
-                            .await?;
-                    }
+        }
+        // Replaced data no longer counts, and a new file counts as one more.
+        let (freed, created) = match existing {
+            Some(size) => ((size - offset) as i64, 0),
+            None => (0, 1),
+        };
+        let quota = self.user.quota.as_ref();
+        if let Err(err) = self
+            .disk_usage
+            .add(&*self.storage, &self.home, &path, -freed, created, quota)
+            .await
+        {
+            error!(self.logger, "Upload of {:?} refused: {}", path, err);
+            self.audit(
+                "upload",
+                Some(&path),
+                None,
+                Outcome::Failure(err.to_string()),
+            )
+            .await;
+            self.control_stream
+                .write_all(b"552 Exceeded storage allocation.\r\n")
+                .await?;
+            self.transfer_mode = TransferMod::Disable;
+            return Ok(());
+        }
+        // Only touch the file once the data connection is there, so that a
+        // failed STOR leaves it as it was.
+        let mut data_stream = match self.open_data_connection().await? {
+            Some(data_stream) => data_stream,
+            None => {
+                let _ = self
+                    .disk_usage
+                    .add(&*self.storage, &self.home, &path, freed, -created, None)
+                    .await;
+                return Ok(());
+            }
+        };
+        // Resumed and appended uploads add to the file in place.
+        let target = if self.config.atomic_uploads && offset == 0 {
+            temp_path(&path)
+        } else {
+            path.clone()
+        };
+        let opened = self.storage.open_write(&target, offset).await;
+        if opened.is_err() {
+            let _ = self
+                .disk_usage
+                .add(&*self.storage, &self.home, &path, freed, -created, None)
+                .await;
+            self.audit("upload", Some(&path), None, Outcome::from(&opened))
+                .await;
+        }
+        let mut file = match opened {
+            Ok(file) => file,
+            Err(_) => {
+                self.control_stream
+                    .write_all(b"553 Could not create file.\r\n")
+                    .await?;
+                return Ok(());
+            }
+        };
+        self.control_stream
+            .write_all(b"150 Ok to send data.\r\n")
+            .await?;
+        let started = Instant::now();
+        let (mut transferred, mut stored) = (0, 0);
+        let room = max_size.map(|max| max.saturating_sub(offset));
+        let result = self
+            .receive_inner(
+                &path,
+                &mut file,
+                &mut data_stream,
+                (&mut transferred, &mut stored),
+                room,
+            )
+            .await;
+        drop((data_stream, file));
+        let result = match result {
+            Ok(_) if target != path => self.storage.rename(&target, &path).await,
+            result => result,
+        };
+        self.metrics
+            .transfer(Direction::Upload, transferred, started.elapsed());
+        self.log_transfer(
+            &path,
+            Direction::Upload,
+            transferred,
+            started,
+            result.is_ok(),
+        )
+        .await;
+        self.audit("upload", Some(&path), None, Outcome::from(&result))
+            .await;
+        match &result {
+            Ok(_) => {
+                self.control_stream
+                    .write_all(b"226 Transfer complete.\r\n")
+                    .await?;
+                let kind = if append {
+                    HookEvent::Append
+                } else {
+                    HookEvent::Upload
+                };
+                self.notify(kind, &path, None, None).await;
+            }
+            Err(err) if is_over_quota(err) || is_too_large(err) => {
+                error!(self.logger, "Upload of {:?} stopped: {}", path, err);
+                // Undo what arrived. A resumed or appended file keeps what it
+                // held before, while one that is new or was being replaced has
+                // nothing left worth keeping.
+                if target != path {
+                    self.abandon(&target, &path, false).await;
+                    self.settle(&path, offset + stored).await;
+                } else if existing.is_some() && (append || offset > 0) {
+                    self.cut(&path, offset).await;
+                } else {
+                    self.discard(&path).await;
                 }
-                Err(err) => {
-                    error!(self.logger, "Unexpected data connection: {}", err);
-                    self.control_stream
-                        .write(b"426 Transfer aborted.\r\n")
-                        .await?;
+                let reply: &[u8] = if is_too_large(err) {
+                    b"552 File exceeds the maximum upload size.\r\n"
+                } else {
+                    b"552 Exceeded storage allocation.\r\n"
+                };
+                self.control_stream.write_all(reply).await?;
+            }
+            Err(err) => {
+                error!(self.logger, "Error during STOR: {}", err);
+                if target != path {
+                    // A partial replacement must not take the place of the
+                    // whole file it was to replace.
+                    let keep = self.config.keep_partial_uploads && existing.is_none();
+                    self.abandon(&target, &path, keep).await;
                 }
-            },
-            TransferMod::Disable => {
+                // Some of the data may not have made it to the storage.
+                self.settle(&path, offset + stored).await;
                 self.control_stream
-                    .write(b"425 Use PORT or PASV first.\r\n")
+                    .write_all(b"426 Transfer aborted.\r\n")
                     .await?;
             }
         }
-        self.transfer_mode = TransferMod::Disable;
         Ok(())
     }
 
+    /// Deal with `temp`, what arrived of a failed atomic upload to `path`:
+    /// move it into place when `keep`, or else remove it.
+    async fn abandon(&self, temp: &Path, path: &Path, keep: bool) {
+        if keep {
+            match self.storage.rename(temp, path).await {
+                Ok(_) => return,
+                Err(err) => error!(self.logger, "Failed to keep partial {:?}: {}", path, err),
+            }
+        }
+        if let Err(err) = self.storage.remove(temp).await {
+            error!(self.logger, "Failed to remove {:?}: {}", temp, err);
+        }
+    }
+
+    /// Correct the usage of a failed upload to `path`, counted so far as a
+    /// file of `counted` bytes, to what the storage now holds there.
+    async fn settle(&self, path: &Path, counted: u64) {
+        let (bytes, files) = match self.storage.stat(path).await {
+            Ok(meta) => (meta.size as i64, 1),
+            Err(_) => (0, 0),
+        };
+        let _ = self
+            .disk_usage
+            .add(
+                &*self.storage,
+                &self.home,
+                path,
+                bytes - counted as i64,
+                files - 1,
+                None,
+            )
+            .await;
+    }
+
+    /// Remove the partial upload at `path`.
+    async fn discard(&self, path: &Path) {
+        let size = match self.storage.stat(path).await {
+            Ok(meta) => meta.size as i64,
+            Err(_) => return,
+        };
+        match self.storage.remove(path).await {
+            Ok(_) => {
+                let _ = self
+                    .disk_usage
+                    .add(&*self.storage, &self.home, path, -size, -1, None)
+                    .await;
+            }
+            Err(err) => error!(self.logger, "Failed to remove {:?}: {}", path, err),
+        }
+    }
+
+    /// Cut the file at `path` back to `len` bytes, dropping what a failed
+    /// upload added to it.
+    async fn cut(&self, path: &Path, len: u64) {
+        let size = match self.storage.stat(path).await {
+            Ok(meta) => meta.size,
+            Err(_) => return,
+        };
+        // Opening a file for writing truncates it at the offset.
+        let result = match self.storage.open_write(path, len).await {
+            Ok(mut file) => file.shutdown().await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(_) => {
+                let freed = size.saturating_sub(len) as i64;
+                let _ = self
+                    .disk_usage
+                    .add(&*self.storage, &self.home, path, -freed, 0, None)
+                    .await;
+            }
+            Err(err) => error!(self.logger, "Failed to truncate {:?}: {}", path, err),
+        }
+    }
+
+    /// Copy the data connection to `file`, the upload to `path`, which may
+    /// grow by `room` bytes at most. Going past it fails with a [`TooLarge`]
+    /// error. The bytes received and those counted as stored are added up in
+    /// `progress`.
     pub async fn receive_inner(
         &mut self,
-        path: &str,
+        path: &Path,
+        file: &mut WriteStream,
         data_stream: &mut TcpStream,
+        progress: (&mut u64, &mut u64),
+        mut room: Option<u64>,
     ) -> tokio::io::Result<()> {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(self.current_path.join(path))
-            .await?;
+        let (transferred, stored) = progress;
+        let mut buffer = [0; 32768];
         match self.transfer_type {
             TransferType::Ascii => {
-                let mut buffer = [0; 32768];
+                let mut converted = Vec::with_capacity(buffer.len() + 1);
+                // Whether the previous byte was a CR not yet written out.
+                let mut carriage = false;
                 loop {
                     let len = data_stream.read(&mut buffer).await?;
                     if len == 0 {
                         break;
                     }
-                    let data = buffer[..len].lines().collect::<Result<Vec<String>, _>>()?;
-                    for chunk in data {
-                        file.write_all(chunk.as_bytes()).await?;
-                        file.write_all(b"\r\n").await?;
+                    *transferred += len as u64;
+                    converted.clear();
+                    for &byte in buffer[..len].iter() {
+                        if carriage && byte != b'\n' {
+                            converted.push(b'\r');
+                        }
+                        carriage = byte == b'\r';
+                        if !carriage {
+                            converted.push(byte);
+                        }
                     }
+                    fit(&mut room, converted.len())?;
+                    self.charge(path, converted.len()).await?;
+                    *stored += converted.len() as u64;
+                    file.write_all(&converted).await?;
                 }
-            }
-            TransferType::Binary => {
-                let mut buffer = [0; 32768];
-                loop {
-                    let len = data_stream.read(&mut buffer).await?;
-                    if len == 0 {
-                        break;
-                    }
-                    file.write_all(&buffer[..len]).await?;
+                if carriage {
+                    fit(&mut room, 1)?;
+                    self.charge(path, 1).await?;
+                    *stored += 1;
+                    file.write_all(b"\r").await?;
                 }
             }
+            TransferType::Binary => loop {
+                let len = data_stream.read(&mut buffer).await?;
+                if len == 0 {
+                    break;
+                }
+                *transferred += len as u64;
+                fit(&mut room, len)?;
+                self.charge(path, len).await?;
+                *stored += len as u64;
+                file.write_all(&buffer[..len]).await?;
+            },
         }
-        file.flush().await?;
+        // Some backends only store the file once it is shut down.
+        file.shutdown().await?;
         Ok(())
     }
+
+    /// Count `len` more bytes as stored at `path`, within the user's quota.
+    async fn charge(&self, path: &Path, len: usize) -> tokio::io::Result<()> {
+        let quota = self.user.quota.as_ref();
+        self.disk_usage
+            .add(&*self.storage, &self.home, path, len as i64, 0, quota)
+            .await
+    }
+}
+
+/// A hidden name next to `path` for an upload in progress.
+fn temp_path(path: &Path) -> PathBuf {
+    static UPLOADS: AtomicU64 = AtomicU64::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let upload = UPLOADS.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}-{}.part", name, std::process::id(), upload))
+}
+
+/// Take `len` bytes out of `room`, if limited, failing when it is too small.
+fn fit(room: &mut Option<u64>, len: usize) -> io::Result<()> {
+    if let Some(room) = room {
+        *room = room
+            .checked_sub(len as u64)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::FileTooLarge, TooLarge))?;
+    }
+    Ok(())
 }
+
+fn is_too_large(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|x| x.is::<TooLarge>())
+}
+
+/// An upload going past the user's maximum upload size.
+#[derive(Debug)]
+struct TooLarge;
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("file exceeds the maximum upload size")
+    }
+}
+
+impl Error for TooLarge {}
@@ -0,0 +1,81 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use crate::utils::config::HookEvent;
+use slog::debug;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+impl FTPSession {
+    pub async fn delete_file(&mut self, path: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        let path = self.resolve_path(path);
+        let result = match self.storage.stat(&path).await {
+            Ok(meta) if !meta.is_dir() => {
+                let removed = self.storage.remove(&path).await;
+                if removed.is_ok() {
+                    let size = meta.size as i64;
+                    let _ = self
+                        .disk_usage
+                        .add(&*self.storage, &self.home, &path, -size, -1, None)
+                        .await;
+                }
+                removed.map(|_| meta.size)
+            }
+            Ok(_) => Err(std::io::Error::other("Is a directory")),
+            Err(err) => Err(err),
+        };
+        self.audit("delete", Some(&path), None, (&result).into())
+            .await;
+        match result {
+            Ok(size) => {
+                self.control_stream
+                    .write_all(b"250 Delete operation successful.\r\n")
+                    .await?;
+                self.notify(HookEvent::Delete, &path, None, Some(size))
+                    .await;
+            }
+            Err(err) => {
+                debug!(self.logger, "Failed to delete {:?}: {}", path, err);
+                self.control_stream
+                    .write_all(b"550 Delete operation failed.\r\n")
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn remove_directory(&mut self, path: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        let path = self.resolve_path(path);
+        let result = match self.storage.stat(&path).await {
+            Ok(meta) if meta.is_dir() && path != Path::new("/") => self.storage.remove(&path).await,
+            Ok(_) => Err(std::io::Error::other("Not a removable directory")),
+            Err(err) => Err(err),
+        };
+        self.audit("rmdir", Some(&path), None, (&result).into())
+            .await;
+        if let Err(err) = result {
+            debug!(self.logger, "Failed to remove {:?}: {}", path, err);
+            self.control_stream
+                .write_all(b"550 Remove directory operation failed.\r\n")
+                .await?;
+        } else {
+            self.control_stream
+                .write_all(b"250 Remove directory operation successful.\r\n")
+                .await?;
+        }
+        Ok(())
+    }
+}
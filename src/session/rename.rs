@@ -0,0 +1,76 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use crate::{audit::Outcome, utils::config::HookEvent};
+use slog::debug;
+use tokio::io::AsyncWriteExt;
+
+impl FTPSession {
+    pub async fn rename_from(&mut self, path: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        let path = self.resolve_path(path);
+        if self.storage.stat(&path).await.is_ok() {
+            self.rename_source = Some(path);
+            self.control_stream
+                .write_all(b"350 Ready for RNTO.\r\n")
+                .await?;
+        } else {
+            self.control_stream
+                .write_all(b"550 RNFR command failed.\r\n")
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn rename_to(&mut self, path: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        let from = match self.rename_source.take() {
+            Some(from) => from,
+            None => {
+                self.control_stream
+                    .write_all(b"503 RNFR required first.\r\n")
+                    .await?;
+                return Ok(());
+            }
+        };
+        let to = self.resolve_path(path);
+        if !self.name_allowed("rename", &to, Some(&from)).await? {
+            return Ok(());
+        }
+        let replaced = self.storage.stat(&to).await.is_ok();
+        let result = self.storage.rename(&from, &to).await;
+        // The replaced file no longer counts, and what moved to another
+        // directory may have left a home or entered one.
+        if result.is_ok() && (replaced || from.parent() != to.parent()) {
+            self.disk_usage.invalidate();
+        }
+        let outcome = Outcome::from(&result);
+        self.audit("rename", Some(&from), Some(&to), outcome).await;
+        if let Err(err) = result {
+            debug!(
+                self.logger,
+                "Failed to rename {:?} to {:?}: {}", from, to, err
+            );
+            self.control_stream
+                .write_all(b"550 Rename failed.\r\n")
+                .await?;
+        } else {
+            self.control_stream
+                .write_all(b"250 Rename successful.\r\n")
+                .await?;
+            self.notify(HookEvent::Rename, &to, Some(&from), None).await;
+        }
+        Ok(())
+    }
+}
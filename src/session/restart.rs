@@ -0,0 +1,31 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use tokio::io::AsyncWriteExt;
+
+impl FTPSession {
+    /// Start the next RETR or STOR `offset` bytes into the file.
+    pub async fn restart(&mut self, offset: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        match offset.parse() {
+            Ok(offset) => {
+                self.restart_offset = offset;
+                self.control_stream
+                    .write_all(format!("350 Restarting at {}.\r\n", offset).as_bytes())
+                    .await?;
+            }
+            Err(_) => {
+                self.control_stream
+                    .write_all(b"501 Invalid restart offset.\r\n")
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
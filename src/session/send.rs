@@ -2,125 +2,108 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use super::{FTPSession, TransferMod, TransferType};
+use crate::{audit::Outcome, metrics::Direction, storage::ReadStream};
 use slog::error;
-use std::net::SocketAddr;
+use std::time::Instant;
 use tokio::{
-    fs::OpenOptions,
     io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpSocket, TcpStream},
+    net::TcpStream,
 };
 
 impl FTPSession {
     pub async fn send(&mut self, path: &str) -> tokio::io::Result<()> {
         if !self.is_logged_in {
             self.control_stream
-                .write(b"530 Please login with USER and PASS.\r\n")
+                .write_all(b"530 Please login with USER and PASS.\r\n")
                 .await?;
             return Ok(());
         }
-        match &self.transfer_mode {
-            TransferMod::Active(remote) => {
-                let (ip, port) = (self.config.listen, self.config.port - 1);
-                let local = TcpSocket::new_v4()?;
-                local.set_reuseaddr(true)?;
-                match local.bind(SocketAddr::new(ip, port)) {
-                    Ok(_) => match local.connect(*remote).await {
-                        Ok(mut data_stream) => {
-                            self.control_stream
-                                .write(b"150 Opening BINARY mode data connection.\r\n")
-                                .await?;
-                            if let Err(err) = self.send_inner(path, &mut data_stream).await {
-                                error!(self.logger, "Error during RETR: {}", err);
-                                self.control_stream
-                                    .write(b"426 Transfer aborted.\r\n")
-                                    .await?;
-                            } else {
-                                self.control_stream
-                                    .write(b"226 Transfer complete.\r\n")
-                                    .await?;
-                            }
-                        }
-                        Err(err) => {
-                            error!(self.logger, "Failed to connect to remote: {}", err);
-                            self.control_stream
-                                .write(b"425 Can't open data connection.\r\n")
-                                .await?;
-                        }
-                    },
-                    Err(err) => {
-                        error!(self.logger, "Failed to listening port {}: {}", port, err);
-                        self.control_stream
-                            .write(b"425 Server data connection close.\r\n")
-                            .await?;
-                    }
-                }
-            }
-            TransferMod::Passive(server) => match server.accept().await {
-                Ok((mut data_stream, _)) => {
-                    self.control_stream
-                        .write(b"150 Opening BINARY mode data connection.\r\n")
-                        .await?;
-                    if let Err(err) = self.send_inner(path, &mut data_stream).await {
-                        error!(self.logger, "Error during send: {}", err);
-                        self.control_stream
-                            .write(b"426 Transfer aborted.\r\n")
-                            .await?;
-                    } else {
-                        self.control_stream
-                            .write(b"226 Transfer complete.\r\n")
-                            .await?;
-                    }
+        let path = self.resolve_path(path);
+        let offset = std::mem::take(&mut self.restart_offset);
+        let mut file = match self.storage.open_read(&path, offset).await {
+            Ok(file) => file,
+            Err(err) => {
+                if err.kind() == std::io::ErrorKind::PermissionDenied {
+                    let outcome = Outcome::Denied(err.to_string());
+                    self.audit("download", Some(&path), None, outcome).await;
                 }
-                Err(err) => {
-                    error!(self.logger, "Unexpected data connection: {}", err);
-                    self.control_stream
-                        .write(b"426 Transfer aborted.\r\n")
-                        .await?;
-                }
-            },
-            TransferMod::Disable => {
                 self.control_stream
-                    .write(b"425 Use PORT or PASV first.\r\n")
+                    .write_all(b"550 Failed to open file.\r\n")
                     .await?;
+                self.transfer_mode = TransferMod::Disable;
+                return Ok(());
             }
+        };
+        let mut data_stream = match self.open_data_connection().await? {
+            Some(data_stream) => data_stream,
+            None => return Ok(()),
+        };
+        self.control_stream
+            .write_all(b"150 Opening BINARY mode data connection.\r\n")
+            .await?;
+        let started = Instant::now();
+        let mut transferred = 0;
+        let result = self
+            .send_inner(&mut file, &mut data_stream, &mut transferred)
+            .await;
+        drop(data_stream);
+        self.metrics
+            .transfer(Direction::Download, transferred, started.elapsed());
+        self.log_transfer(
+            &path,
+            Direction::Download,
+            transferred,
+            started,
+            result.is_ok(),
+        )
+        .await;
+        if let Err(err) = &result {
+            error!(self.logger, "Error during RETR: {}", err);
+            self.control_stream
+                .write_all(b"426 Transfer aborted.\r\n")
+                .await?;
+        } else {
+            self.control_stream
+                .write_all(b"226 Transfer complete.\r\n")
+                .await?;
         }
-        self.transfer_mode = TransferMod::Disable;
         Ok(())
     }
 
     pub async fn send_inner(
         &mut self,
-        path: &str,
+        file: &mut ReadStream,
         data_stream: &mut TcpStream,
+        transferred: &mut u64,
     ) -> tokio::io::Result<()> {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .open(self.current_path.join(path))
-            .await?;
+        let mut buffer = [0; 32768];
         match self.transfer_type {
             TransferType::Ascii => {
-                let mut buffer = [0; 32768];
+                let mut converted = Vec::with_capacity(buffer.len() * 2);
                 loop {
                     let len = file.read(&mut buffer).await?;
                     if len == 0 {
                         break;
                     }
-                    for chunk in buffer[..len].split(|&x| x == b'\n') {
-                        data_stream.write_all(chunk).await?;
-                        data_stream.write_all(b"\r\n").await?;
+                    converted.clear();
+                    for &byte in buffer[..len].iter() {
+                        if byte == b'\n' {
+                            converted.push(b'\r');
+                        }
+                        converted.push(byte);
                     }
+                    data_stream.write_all(&converted).await?;
+                    *transferred += converted.len() as u64;
                 }
             }
-            TransferType::Binary => {
-                let mut buffer = [0; 32768];
-                loop {
-                    let len = file.read(&mut buffer).await?;
-                    if len == 0 {
-                        break;
-                    }
-                    data_stream.write(&buffer[..len]).await?;
+            TransferType::Binary => loop {
+                let len = file.read(&mut buffer).await?;
+                if len == 0 {
+                    break;
                 }
-            }
+                data_stream.write_all(&buffer[..len]).await?;
+                *transferred += len as u64;
+            },
         }
         Ok(())
     }
@@ -0,0 +1,15 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use tokio::io::AsyncWriteExt;
+
+impl FTPSession {
+    pub async fn service_closing(&mut self) -> tokio::io::Result<()> {
+        self.control_stream
+            .write_all(b"421 Service not available, closing control connection.\r\n")
+            .await?;
+        self.control_stream.flush().await?;
+        Ok(())
+    }
+}
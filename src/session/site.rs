@@ -0,0 +1,63 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::FTPSession;
+use slog::error;
+use tokio::io::AsyncWriteExt;
+
+impl FTPSession {
+    pub async fn site(&mut self, para: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        match para.split(' ').next() {
+            Some(command) if command.eq_ignore_ascii_case("QUOTA") => self.site_quota().await,
+            _ => {
+                self.control_stream
+                    .write_all(b"504 Unknown SITE command.\r\n")
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Show how much of the user's quota is used.
+    async fn site_quota(&mut self) -> tokio::io::Result<()> {
+        let quota = match &self.user.quota {
+            Some(quota) => quota.clone(),
+            None => {
+                self.control_stream
+                    .write_all(format!("200 No quota for {}.\r\n", self.current_user).as_bytes())
+                    .await?;
+                return Ok(());
+            }
+        };
+        let usage = match self.disk_usage.get(&*self.storage, &self.home).await {
+            Ok(usage) => usage,
+            Err(err) => {
+                error!(self.logger, "Failed to count storage usage: {}", err);
+                self.control_stream
+                    .write_all(b"451 Could not count storage usage.\r\n")
+                    .await?;
+                return Ok(());
+            }
+        };
+        let limit = |limit: Option<u64>| match limit {
+            Some(limit) => limit.to_string(),
+            None => String::from("unlimited"),
+        };
+        let reply = format!(
+            "200-Quota for {}:\r\n Bytes: {} of {} used.\r\n Files: {} of {} used.\r\n200 End.\r\n",
+            self.current_user,
+            usage.bytes,
+            limit(quota.bytes),
+            usage.files,
+            limit(quota.files)
+        );
+        self.control_stream.write_all(reply.as_bytes()).await?;
+        Ok(())
+    }
+}
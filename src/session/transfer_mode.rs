@@ -2,31 +2,69 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use super::{FTPSession, TransferMod};
-use crate::utils::net::{parse_ipv4_addr, print_ipv4_addr};
-use slog::debug;
+use crate::utils::net::{parse_extended_addr, parse_ipv4_addr, print_ipv4_addr};
+use slog::{debug, warn};
 use std::net::SocketAddr;
-use tokio::{io::AsyncWriteExt, net::TcpListener};
+use tokio::{
+    io::AsyncWriteExt,
+    time::{Duration, Instant},
+};
 
 impl FTPSession {
     pub async fn set_active(&mut self, remote: &str) -> tokio::io::Result<()> {
         if !self.is_logged_in {
             self.control_stream
-                .write(b"530 Please login with USER and PASS.\r\n")
+                .write_all(b"530 Please login with USER and PASS.\r\n")
                 .await?;
             return Ok(());
         }
-        let remote = parse_ipv4_addr(remote);
+        self.enter_active("PORT", parse_ipv4_addr(remote)).await
+    }
+
+    pub async fn set_extended_active(&mut self, remote: &str) -> tokio::io::Result<()> {
+        if !self.is_logged_in {
+            self.control_stream
+                .write_all(b"530 Please login with USER and PASS.\r\n")
+                .await?;
+            return Ok(());
+        }
+        let protocol = remote.chars().next().and_then(|x| remote.split(x).nth(1));
+        if protocol.is_some_and(|x| x != "1" && x != "2") {
+            self.control_stream
+                .write_all(b"522 Network protocol not supported, use (1,2).\r\n")
+                .await?;
+            self.transfer_mode = TransferMod::Disable;
+            return Ok(());
+        }
+        self.enter_active("EPRT", parse_extended_addr(remote)).await
+    }
+
+    /// Prepare to connect to `remote`, unless it could be used to make the
+    /// server attack another host: only the client's own unprivileged ports
+    /// are accepted, or any unprivileged port for users allowed FXP.
+    async fn enter_active(
+        &mut self,
+        verb: &str,
+        remote: Option<SocketAddr>,
+    ) -> tokio::io::Result<()> {
         self.transfer_mode = match remote {
+            Some(remote) if remote.port() < 1024 || !self.data_peer_allowed(remote.ip()) => {
+                warn!(self.logger, "Refused {} to {}", verb, remote);
+                self.control_stream
+                    .write_all(b"504 Refusing to connect to that address.\r\n")
+                    .await?;
+                TransferMod::Disable
+            }
             Some(remote) => {
                 debug!(self.logger, "Try entering active mode with {}", remote);
                 self.control_stream
-                    .write(b"200 PORT command successful.\r\n")
+                    .write_all(format!("200 {} command successful.\r\n", verb).as_bytes())
                     .await?;
                 TransferMod::Active(remote)
             }
             None => {
                 self.control_stream
-                    .write(b"501 Illegal address.\r\n")
+                    .write_all(b"501 Illegal address.\r\n")
                     .await?;
                 TransferMod::Disable
             }
@@ -37,19 +75,34 @@ impl FTPSession {
     pub async fn set_passive(&mut self) -> tokio::io::Result<()> {
         if !self.is_logged_in {
             self.control_stream
-                .write(b"530 Please login with USER and PASS.\r\n")
+                .write_all(b"530 Please login with USER and PASS.\r\n")
                 .await?;
             return Ok(());
         }
-        self.transfer_mode = match TcpListener::bind(SocketAddr::new(self.config.listen, 0)).await {
-            Ok(listener) => {
+        if let TransferMod::Passive(listener, ..) = &self.transfer_mode {
+            debug!(
+                self.logger,
+                "Closing unused passive port {}",
+                listener.local_addr()?
+            );
+        }
+        // Give back the previous port before taking another.
+        self.transfer_mode = TransferMod::Disable;
+        let opened = self.passive_ports.open(
+            self.config.listen,
+            self.config.passive_ports.as_ref(),
+            self.config.max_passive_ports,
+            &self.metrics,
+        );
+        self.transfer_mode = match opened {
+            Ok((listener, lease)) => {
                 debug!(
                     self.logger,
                     "Try entering passive mode with {}",
                     listener.local_addr()?
                 );
                 self.control_stream
-                    .write(
+                    .write_all(
                         format!(
                             "227 Entering Passive Mode {}.\r\n",
                             print_ipv4_addr(listener.local_addr()?)
@@ -57,12 +110,20 @@ impl FTPSession {
                         .as_bytes(),
                     )
                     .await?;
-                TransferMod::Passive(listener)
+                let deadline = Instant::now() + Duration::from_secs(self.config.passive_timeout);
+                TransferMod::Passive(listener, lease, deadline)
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+                warn!(self.logger, "No passive port available: {}", err);
+                self.control_stream
+                    .write_all(b"425 No passive port available, try again later.\r\n")
+                    .await?;
+                TransferMod::Disable
             }
             Err(err) => {
                 debug!(self.logger, "Create socket unsuccessfully: {}", err);
                 self.control_stream
-                    .write(b"421 Could not create socket.\r\n")
+                    .write_all(b"421 Could not create socket.\r\n")
                     .await?;
                 TransferMod::Disable
             }
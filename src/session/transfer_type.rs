@@ -10,16 +10,16 @@ impl FTPSession {
         if transfer_type == "A" {
             self.transfer_type = TransferType::Ascii;
             self.control_stream
-                .write(b"200 Switching to ASCII mode.\r\n")
+                .write_all(b"200 Switching to ASCII mode.\r\n")
                 .await?;
         } else if transfer_type == "I" {
             self.transfer_type = TransferType::Binary;
             self.control_stream
-                .write(b"200 Switching to Binary mode.\r\n")
+                .write_all(b"200 Switching to Binary mode.\r\n")
                 .await?;
         } else {
             self.control_stream
-                .write(b"504 Unsupported type.\r\n")
+                .write_all(b"504 Unsupported type.\r\n")
                 .await?;
         }
         Ok(())
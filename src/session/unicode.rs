@@ -7,7 +7,7 @@ use tokio::io::AsyncWriteExt;
 impl FTPSession {
     pub async fn unicode(&mut self) -> tokio::io::Result<()> {
         self.control_stream
-            .write(b"200 Always in UTF8 mode.\r\n")
+            .write_all(b"200 Always in UTF8 mode.\r\n")
             .await?;
         Ok(())
     }
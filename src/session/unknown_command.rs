@@ -7,7 +7,7 @@ use tokio::io::AsyncWriteExt;
 impl FTPSession {
     pub async fn unknown_command(&mut self) -> tokio::io::Result<()> {
         self.control_stream
-            .write(b"500 Unknown command.\r\n")
+            .write_all(b"500 Unknown command.\r\n")
             .await?;
         Ok(())
     }
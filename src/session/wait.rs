@@ -6,7 +6,7 @@ use tokio::io::AsyncWriteExt;
 
 impl FTPSession {
     pub async fn wait(&mut self) -> tokio::io::Result<()> {
-        self.control_stream.write(b"200 NOOP ok.\r\n").await?;
+        self.control_stream.write_all(b"200 NOOP ok.\r\n").await?;
         Ok(())
     }
 }
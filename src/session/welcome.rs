@@ -6,7 +6,9 @@ use tokio::io::AsyncWriteExt;
 
 impl FTPSession {
     pub async fn welcome(&mut self) -> tokio::io::Result<()> {
-        self.control_stream.write(b"220 KiraFTP v1.1.1\r\n").await?;
+        self.control_stream
+            .write_all(b"220 KiraFTP v1.1.1\r\n")
+            .await?;
         Ok(())
     }
 }
@@ -0,0 +1,71 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{FTPSession, TransferType};
+use crate::metrics::Direction;
+use slog::error;
+use std::{path::Path, time::Instant};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+impl FTPSession {
+    /// Append a wu-ftpd style xferlog line for a transfer that ended, if an
+    /// xferlog is configured.
+    pub(super) async fn log_transfer(
+        &self,
+        path: &Path,
+        direction: Direction,
+        bytes: u64,
+        started: Instant,
+        complete: bool,
+    ) {
+        let xferlog = match &self.config.xferlog {
+            Some(xferlog) => xferlog,
+            None => return,
+        };
+        let remote = match self.control_stream.peer_addr() {
+            Ok(remote) => remote.ip().to_string(),
+            Err(_) => String::from("unknown"),
+        };
+        // Fields are separated by spaces, so a name cannot contain any.
+        let filename: String = path
+            .to_string_lossy()
+            .chars()
+            .map(|x| if x.is_whitespace() { '_' } else { x })
+            .collect();
+        let line = format!(
+            "{} {} {} {} {} {} _ {} {} {} ftp 0 * {}\n",
+            chrono::Local::now().format("%a %b %e %H:%M:%S %Y"),
+            started.elapsed().as_secs_f64().round().max(1.0) as u64,
+            remote,
+            bytes,
+            filename,
+            match self.transfer_type {
+                TransferType::Ascii => 'a',
+                TransferType::Binary => 'b',
+            },
+            match direction {
+                Direction::Upload => 'i',
+                Direction::Download => 'o',
+            },
+            if self.is_anonymous { 'a' } else { 'r' },
+            self.current_user,
+            if complete { 'c' } else { 'i' },
+        );
+        let written = async {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(xferlog)
+                .await?;
+            file.write_all(line.as_bytes()).await
+        };
+        if let Err(err) = written.await {
+            error!(
+                self.logger,
+                "Failed to write xferlog {}: {}",
+                xferlog.display(),
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{DirEntry, Metadata, ReadStream, StorageBackend, WriteStream};
+use crate::utils::fs::resolve;
+use async_trait::async_trait;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Serves a directory of another backend as `/`.
+pub struct Chroot {
+    inner: Arc<dyn StorageBackend>,
+    root: PathBuf,
+}
+
+impl Chroot {
+    pub fn new(inner: Arc<dyn StorageBackend>, root: impl AsRef<Path>) -> Self {
+        Self {
+            inner,
+            root: resolve("/", root),
+        }
+    }
+
+    fn inner_path(&self, path: &Path) -> PathBuf {
+        let path = resolve("/", path);
+        self.root.join(path.strip_prefix("/").unwrap_or(&path))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for Chroot {
+    async fn stat(&self, path: &Path) -> tokio::io::Result<Metadata> {
+        self.inner.stat(&self.inner_path(path)).await
+    }
+
+    async fn list(&self, path: &Path) -> tokio::io::Result<Vec<DirEntry>> {
+        self.inner.list(&self.inner_path(path)).await
+    }
+
+    async fn open_read(&self, path: &Path, offset: u64) -> tokio::io::Result<ReadStream> {
+        self.inner.open_read(&self.inner_path(path), offset).await
+    }
+
+    async fn open_write(&self, path: &Path, offset: u64) -> tokio::io::Result<WriteStream> {
+        self.inner.open_write(&self.inner_path(path), offset).await
+    }
+
+    async fn mkdir(&self, path: &Path) -> tokio::io::Result<()> {
+        self.inner.mkdir(&self.inner_path(path)).await
+    }
+
+    async fn remove(&self, path: &Path) -> tokio::io::Result<()> {
+        self.inner.remove(&self.inner_path(path)).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> tokio::io::Result<()> {
+        let (from, to) = (self.inner_path(from), self.inner_path(to));
+        self.inner.rename(&from, &to).await
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        self.inner.local_path(&self.inner_path(path))
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{DirEntry, Metadata, ReadStream, StorageBackend, WriteStream};
+use crate::utils::fs::resolve;
+use async_trait::async_trait;
+use std::{
+    io::{Error, ErrorKind, SeekFrom},
+    os::unix::prelude::*,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncSeekExt,
+};
+
+/// Serves a directory on the local filesystem.
+pub struct LocalFileSystem {
+    root: PathBuf,
+}
+
+impl LocalFileSystem {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn real_path(&self, path: &Path) -> PathBuf {
+        let path = resolve("/", path);
+        self.root.join(path.strip_prefix("/").unwrap_or(&path))
+    }
+
+    /// Map `path` into the root like [`Self::real_path`], refusing it when
+    /// symlinks lead out of the root. With `follow` false, a symlink at the
+    /// end of the path is not looked through, as for removing or renaming it.
+    async fn confined(&self, path: &Path, follow: bool) -> tokio::io::Result<PathBuf> {
+        let real = self.real_path(path);
+        let root = fs::canonicalize(&self.root).await?;
+        let mut existing = if follow {
+            real.as_path()
+        } else {
+            real.parent().unwrap_or(&real)
+        };
+        // The deepest part of the path that exists decides where it leads.
+        while fs::symlink_metadata(existing).await.is_err() {
+            match existing.parent() {
+                Some(parent) => existing = parent,
+                None => break,
+            }
+        }
+        match fs::canonicalize(existing).await {
+            Ok(target) if target.starts_with(&root) => Ok(real),
+            _ => Err(Error::from(ErrorKind::PermissionDenied)),
+        }
+    }
+}
+
+fn metadata(metadata: std::fs::Metadata) -> Metadata {
+    let owner = users::get_user_by_uid(metadata.uid())
+        .map(|user| user.name().to_string_lossy().into_owned())
+        .unwrap_or_else(|| metadata.uid().to_string());
+    let group = users::get_group_by_gid(metadata.gid())
+        .map(|group| group.name().to_string_lossy().into_owned())
+        .unwrap_or_else(|| metadata.gid().to_string());
+    Metadata {
+        mode: metadata.mode(),
+        nlink: metadata.nlink(),
+        owner,
+        group,
+        size: metadata.size(),
+        modified: metadata.modified().unwrap_or(std::time::UNIX_EPOCH),
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalFileSystem {
+    async fn stat(&self, path: &Path) -> tokio::io::Result<Metadata> {
+        Ok(metadata(
+            fs::metadata(self.confined(path, true).await?).await?,
+        ))
+    }
+
+    async fn list(&self, path: &Path) -> tokio::io::Result<Vec<DirEntry>> {
+        let mut dir = fs::read_dir(self.confined(path, true).await?).await?;
+        let mut entries = Vec::new();
+        while let Some(item) = dir.next_entry().await? {
+            if let Ok(meta) = item.metadata().await {
+                entries.push(DirEntry {
+                    name: item.file_name().to_string_lossy().into_owned(),
+                    metadata: metadata(meta),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn open_read(&self, path: &Path, offset: u64) -> tokio::io::Result<ReadStream> {
+        let mut file = File::open(self.confined(path, true).await?).await?;
+        if file.metadata().await?.is_dir() {
+            return Err(Error::other("Is a directory"));
+        }
+        if offset > 0 {
+            file.seek(SeekFrom::Start(offset)).await?;
+        }
+        Ok(Box::new(file))
+    }
+
+    async fn open_write(&self, path: &Path, offset: u64) -> tokio::io::Result<WriteStream> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(offset == 0)
+            .open(self.confined(path, true).await?)
+            .await?;
+        if offset > 0 {
+            file.set_len(offset).await?;
+            file.seek(SeekFrom::Start(offset)).await?;
+        }
+        Ok(Box::new(file))
+    }
+
+    async fn mkdir(&self, path: &Path) -> tokio::io::Result<()> {
+        fs::create_dir(self.confined(path, true).await?).await
+    }
+
+    async fn remove(&self, path: &Path) -> tokio::io::Result<()> {
+        let path = self.confined(path, false).await?;
+        if fs::symlink_metadata(&path).await?.is_dir() {
+            fs::remove_dir(path).await
+        } else {
+            fs::remove_file(path).await
+        }
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> tokio::io::Result<()> {
+        let from = self.confined(from, false).await?;
+        fs::rename(from, self.confined(to, false).await?).await
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(self.real_path(path))
+    }
+}
@@ -0,0 +1,376 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{DirEntry, Metadata, ReadStream, StorageBackend, WriteStream};
+use crate::utils::fs::resolve;
+use async_trait::async_trait;
+use libc::{S_IFDIR, S_IFREG, S_IRUSR, S_IWUSR};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::SystemTime,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const DEFAULT_DIR_MODE: u32 = 0o755;
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// Keeps everything in memory. Contents vanish when the server stops.
+pub struct MemoryFileSystem {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    quota: Option<u64>,
+}
+
+struct State {
+    nodes: HashMap<PathBuf, Node>,
+    /// Bytes held by all files together.
+    used: u64,
+}
+
+struct Node {
+    mode: u32,
+    modified: SystemTime,
+    /// `None` for directories.
+    file: Option<Arc<Mutex<Vec<u8>>>>,
+}
+
+impl Node {
+    fn directory() -> Self {
+        Self {
+            mode: S_IFDIR | DEFAULT_DIR_MODE,
+            modified: SystemTime::now(),
+            file: None,
+        }
+    }
+
+    fn file() -> Self {
+        Self {
+            mode: S_IFREG | DEFAULT_FILE_MODE,
+            modified: SystemTime::now(),
+            file: Some(Arc::new(Mutex::new(Vec::new()))),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.file
+            .as_ref()
+            .map(|data| data.lock().unwrap().len() as u64)
+            .unwrap_or(0)
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            mode: self.mode,
+            nlink: if self.file.is_some() { 1 } else { 2 },
+            owner: String::from("ftp"),
+            group: String::from("ftp"),
+            size: self.size(),
+            modified: self.modified,
+        }
+    }
+}
+
+fn not_found() -> Error {
+    Error::from(ErrorKind::NotFound)
+}
+
+fn denied() -> Error {
+    Error::from(ErrorKind::PermissionDenied)
+}
+
+impl State {
+    fn node(&self, path: &Path) -> tokio::io::Result<&Node> {
+        self.nodes.get(path).ok_or_else(not_found)
+    }
+
+    /// Check that entries may be added to or removed from the parent of
+    /// `path`.
+    fn check_parent(&self, path: &Path) -> tokio::io::Result<()> {
+        let parent = self.node(path.parent().ok_or_else(denied)?)?;
+        if parent.file.is_some() {
+            Err(Error::from(ErrorKind::NotADirectory))
+        } else if parent.mode & S_IWUSR == 0 {
+            Err(denied())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl MemoryFileSystem {
+    /// Create an empty filesystem holding at most `quota` bytes.
+    pub fn new(quota: Option<u64>) -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(PathBuf::from("/"), Node::directory());
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State { nodes, used: 0 }),
+                quota,
+            }),
+        }
+    }
+
+    /// Change the permission bits of a file or directory.
+    pub fn set_permissions(&self, path: impl AsRef<Path>, mode: u32) -> tokio::io::Result<()> {
+        let path = resolve("/", path);
+        let mut state = self.inner.state.lock().unwrap();
+        let node = state.nodes.get_mut(&path).ok_or_else(not_found)?;
+        node.mode = node.mode & !0o7777 | mode & 0o7777;
+        Ok(())
+    }
+
+    /// Bytes currently stored.
+    pub fn used(&self) -> u64 {
+        self.inner.state.lock().unwrap().used
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryFileSystem {
+    async fn stat(&self, path: &Path) -> tokio::io::Result<Metadata> {
+        let path = resolve("/", path);
+        let state = self.inner.state.lock().unwrap();
+        Ok(state.node(&path)?.metadata())
+    }
+
+    async fn list(&self, path: &Path) -> tokio::io::Result<Vec<DirEntry>> {
+        let path = resolve("/", path);
+        let state = self.inner.state.lock().unwrap();
+        let dir = state.node(&path)?;
+        if dir.file.is_some() {
+            return Err(Error::from(ErrorKind::NotADirectory));
+        }
+        if dir.mode & S_IRUSR == 0 {
+            return Err(denied());
+        }
+        let mut entries: Vec<DirEntry> = state
+            .nodes
+            .iter()
+            .filter(|(child, _)| child.parent() == Some(&path))
+            .map(|(child, node)| DirEntry {
+                name: child.file_name().unwrap().to_string_lossy().into_owned(),
+                metadata: node.metadata(),
+            })
+            .collect();
+        entries.sort_by(|x, y| x.name.cmp(&y.name));
+        Ok(entries)
+    }
+
+    async fn open_read(&self, path: &Path, offset: u64) -> tokio::io::Result<ReadStream> {
+        let path = resolve("/", path);
+        let state = self.inner.state.lock().unwrap();
+        let node = state.node(&path)?;
+        let file = node
+            .file
+            .clone()
+            .ok_or_else(|| Error::from(ErrorKind::IsADirectory))?;
+        if node.mode & S_IRUSR == 0 {
+            return Err(denied());
+        }
+        Ok(Box::new(MemoryReader {
+            file,
+            position: offset as usize,
+        }))
+    }
+
+    async fn open_write(&self, path: &Path, offset: u64) -> tokio::io::Result<WriteStream> {
+        let path = resolve("/", path);
+        let mut state = self.inner.state.lock().unwrap();
+        let file = match state.nodes.get(&path) {
+            Some(node) => {
+                let file = node
+                    .file
+                    .clone()
+                    .ok_or_else(|| Error::from(ErrorKind::IsADirectory))?;
+                if node.mode & S_IWUSR == 0 {
+                    return Err(denied());
+                }
+                file
+            }
+            None => {
+                state.check_parent(&path)?;
+                let node = Node::file();
+                let file = node.file.clone().unwrap();
+                state.nodes.insert(path.clone(), node);
+                file
+            }
+        };
+        let mut data = file.lock().unwrap();
+        let len = data.len() as u64;
+        if len > offset {
+            data.truncate(offset as usize);
+            state.used -= len - offset;
+        }
+        drop(data);
+        Ok(Box::new(MemoryWriter {
+            fs: self.inner.clone(),
+            path: Some(path),
+            file,
+            position: offset as usize,
+            unlinked: 0,
+        }))
+    }
+
+    async fn mkdir(&self, path: &Path) -> tokio::io::Result<()> {
+        let path = resolve("/", path);
+        let mut state = self.inner.state.lock().unwrap();
+        if state.nodes.contains_key(&path) {
+            return Err(Error::from(ErrorKind::AlreadyExists));
+        }
+        state.check_parent(&path)?;
+        state.nodes.insert(path, Node::directory());
+        Ok(())
+    }
+
+    async fn remove(&self, path: &Path) -> tokio::io::Result<()> {
+        let path = resolve("/", path);
+        let mut state = self.inner.state.lock().unwrap();
+        let node = state.node(&path)?;
+        if node.file.is_none() && state.nodes.keys().any(|x| x.parent() == Some(&path)) {
+            return Err(Error::from(ErrorKind::DirectoryNotEmpty));
+        }
+        state.check_parent(&path)?;
+        let node = state.nodes.remove(&path).unwrap();
+        state.used -= node.size();
+        Ok(())
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> tokio::io::Result<()> {
+        let (from, to) = (resolve("/", from), resolve("/", to));
+        let mut state = self.inner.state.lock().unwrap();
+        state.node(&from)?;
+        if from == to {
+            return Ok(());
+        }
+        if to.starts_with(&from) {
+            return Err(Error::from(ErrorKind::InvalidInput));
+        }
+        state.check_parent(&from)?;
+        state.check_parent(&to)?;
+        match (state.nodes.get(&from), state.nodes.get(&to)) {
+            (_, None) => {}
+            (Some(source), Some(target)) if source.file.is_some() && target.file.is_some() => {
+                let size = target.size();
+                state.nodes.remove(&to);
+                state.used -= size;
+            }
+            _ => return Err(Error::from(ErrorKind::AlreadyExists)),
+        }
+        let moved: Vec<PathBuf> = state
+            .nodes
+            .keys()
+            .filter(|x| x.starts_with(&from))
+            .cloned()
+            .collect();
+        for path in moved {
+            let node = state.nodes.remove(&path).unwrap();
+            let path = to.join(path.strip_prefix(&from).unwrap());
+            state.nodes.insert(path, node);
+        }
+        Ok(())
+    }
+}
+
+struct MemoryReader {
+    file: Arc<Mutex<Vec<u8>>>,
+    position: usize,
+}
+
+impl AsyncRead for MemoryReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<tokio::io::Result<()>> {
+        let file = self.file.clone();
+        let data = file.lock().unwrap();
+        let rest = data.get(self.position..).unwrap_or(&[]);
+        let len = rest.len().min(buf.remaining());
+        buf.put_slice(&rest[..len]);
+        self.position += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct MemoryWriter {
+    fs: Arc<Inner>,
+    /// Where the file is, followed through renames. `None` once removed.
+    path: Option<PathBuf>,
+    file: Arc<Mutex<Vec<u8>>>,
+    position: usize,
+    /// Bytes written since the file was removed. They count towards the
+    /// quota until the writer is dropped, which frees them.
+    unlinked: u64,
+}
+
+impl MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> tokio::io::Result<usize> {
+        let mut state = self.fs.state.lock().unwrap();
+        let mut data = self.file.lock().unwrap();
+        let end = self.position + buf.len();
+        let holds = |node: &Node| {
+            node.file
+                .as_ref()
+                .is_some_and(|file| Arc::ptr_eq(file, &self.file))
+        };
+        if let Some(path) = &self.path {
+            if !state.nodes.get(path).is_some_and(holds) {
+                self.path = state
+                    .nodes
+                    .iter()
+                    .find(|(_, node)| holds(node))
+                    .map(|(path, _)| path.clone());
+            }
+        }
+        let growth = end.saturating_sub(data.len()) as u64;
+        if let Some(quota) = self.fs.quota {
+            if state.used + growth > quota {
+                return Err(Error::from(ErrorKind::QuotaExceeded));
+            }
+        }
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[self.position..end].copy_from_slice(buf);
+        self.position = end;
+        drop(data);
+        state.used += growth;
+        match &self.path {
+            Some(path) => state.nodes.get_mut(path).unwrap().modified = SystemTime::now(),
+            None => self.unlinked += growth,
+        }
+        Ok(buf.len())
+    }
+}
+
+impl Drop for MemoryWriter {
+    fn drop(&mut self) {
+        self.fs.state.lock().unwrap().used -= self.unlinked;
+    }
+}
+
+impl AsyncWrite for MemoryWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<tokio::io::Result<usize>> {
+        Poll::Ready(self.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<tokio::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<tokio::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Where served files live. Protocol code only talks to a [`StorageBackend`],
+//! with virtual paths that are absolute and already confined to `/`.
+
+mod chroot;
+mod local;
+mod memory;
+#[cfg(feature = "s3")]
+mod s3;
+
+pub use chroot::Chroot;
+pub use local::LocalFileSystem;
+pub use memory::MemoryFileSystem;
+#[cfg(feature = "s3")]
+pub use s3::S3Storage;
+
+use crate::utils::config::{Config, Storage};
+use async_trait::async_trait;
+use libc::{S_IFDIR, S_IFMT};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub type ReadStream = Box<dyn AsyncRead + Send + Unpin>;
+pub type WriteStream = Box<dyn AsyncWrite + Send + Unpin>;
+
+pub struct Metadata {
+    /// File type and permission bits, laid out as in `st_mode`.
+    pub mode: u32,
+    pub nlink: u64,
+    pub owner: String,
+    pub group: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+}
+
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    async fn stat(&self, path: &Path) -> tokio::io::Result<Metadata>;
+
+    async fn list(&self, path: &Path) -> tokio::io::Result<Vec<DirEntry>>;
+
+    /// Open a file for reading, starting `offset` bytes in.
+    async fn open_read(&self, path: &Path, offset: u64) -> tokio::io::Result<ReadStream>;
+
+    /// Open a file for writing at `offset`, creating it if needed. An offset
+    /// of zero truncates the file.
+    async fn open_write(&self, path: &Path, offset: u64) -> tokio::io::Result<WriteStream>;
+
+    async fn mkdir(&self, path: &Path) -> tokio::io::Result<()>;
+
+    /// Remove a file or an empty directory.
+    async fn remove(&self, path: &Path) -> tokio::io::Result<()>;
+
+    async fn rename(&self, from: &Path, to: &Path) -> tokio::io::Result<()>;
+
+    /// Where `path` is on the local filesystem, for backends keeping files
+    /// there.
+    fn local_path(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Create the backend selected by `storage` in the config.
+pub fn from_config(config: &Config) -> tokio::io::Result<Arc<dyn StorageBackend>> {
+    Ok(match &config.storage {
+        Storage::Local => Arc::new(LocalFileSystem::new(&config.path)),
+        Storage::Memory { quota } => Arc::new(MemoryFileSystem::new(*quota)),
+        #[cfg(feature = "s3")]
+        Storage::S3(s3) => Arc::new(S3Storage::new(s3.clone())?),
+        #[cfg(not(feature = "s3"))]
+        Storage::S3(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "kiraftp was built without S3 support",
+            ))
+        }
+    })
+}
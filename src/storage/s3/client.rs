@@ -0,0 +1,360 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! A small S3 REST client covering what the storage backend needs.
+
+use super::signature::{self, canonical_query, uri_encode, Credentials, UNSIGNED_PAYLOAD};
+use crate::utils::config::S3Config;
+use chrono::{DateTime, Utc};
+use reqwest::{header::HeaderMap, Method, Response, StatusCode, Url};
+use serde::Deserialize;
+use std::{
+    io::{Error, ErrorKind},
+    time::SystemTime,
+};
+
+pub struct Object {
+    pub key: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+#[derive(Default)]
+pub struct Listing {
+    pub objects: Vec<Object>,
+    /// Common prefixes, each ending with the delimiter.
+    pub prefixes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<ListedObject>,
+    #[serde(default)]
+    common_prefixes: Vec<CommonPrefix>,
+    #[serde(default)]
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedObject {
+    key: String,
+    size: u64,
+    last_modified: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct CommonPrefix {
+    prefix: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InitiateMultipartUploadResult {
+    upload_id: String,
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    config: S3Config,
+    endpoint: Url,
+}
+
+fn parse_time(time: &str) -> SystemTime {
+    DateTime::parse_from_rfc3339(time)
+        .or_else(|_| DateTime::parse_from_rfc2822(time))
+        .map(SystemTime::from)
+        .unwrap_or(std::time::UNIX_EPOCH)
+}
+
+fn invalid_data(err: impl std::fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+async fn check(response: Response) -> tokio::io::Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let kind = match status {
+        StatusCode::NOT_FOUND => ErrorKind::NotFound,
+        StatusCode::FORBIDDEN => ErrorKind::PermissionDenied,
+        _ => ErrorKind::Other,
+    };
+    let body = response.text().await.unwrap_or_default();
+    Err(Error::new(kind, format!("S3 replied {}: {}", status, body)))
+}
+
+impl Client {
+    pub fn new(config: S3Config) -> tokio::io::Result<Self> {
+        let endpoint = Url::parse(&config.endpoint)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+        if endpoint.host_str().is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "S3 endpoint has no host",
+            ));
+        }
+        Ok(Self {
+            http: reqwest::Client::new(),
+            config,
+            endpoint,
+        })
+    }
+
+    fn url(&self, key: &str, query: &str) -> Url {
+        let mut url = self.endpoint.clone();
+        let base = url.path().trim_end_matches('/').to_string();
+        if self.config.path_style {
+            url.set_path(&format!(
+                "{}/{}/{}",
+                base,
+                uri_encode(&self.config.bucket, true),
+                uri_encode(key, false)
+            ));
+        } else {
+            let host = format!("{}.{}", self.config.bucket, url.host_str().unwrap());
+            url.set_host(Some(&host)).unwrap();
+            url.set_path(&format!("{}/{}", base, uri_encode(key, false)));
+        }
+        url.set_query(if query.is_empty() { None } else { Some(query) });
+        url
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, String)],
+        headers: &[(&str, String)],
+        body: Vec<u8>,
+    ) -> tokio::io::Result<Response> {
+        let query = canonical_query(query);
+        let url = self.url(key, &query);
+        let timestamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap(), port),
+            None => url.host_str().unwrap().to_string(),
+        };
+        let mut signed = vec![
+            (String::from("host"), host),
+            (
+                String::from("x-amz-content-sha256"),
+                String::from(UNSIGNED_PAYLOAD),
+            ),
+            (String::from("x-amz-date"), timestamp.clone()),
+        ];
+        signed.extend(
+            headers
+                .iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), value.clone())),
+        );
+        signed.sort();
+        let authorization = signature::authorization(
+            &signature::Request {
+                method: method.as_str(),
+                path: url.path(),
+                query: &query,
+                headers: &signed,
+                payload_hash: UNSIGNED_PAYLOAD,
+                timestamp: &timestamp,
+            },
+            &Credentials {
+                access_key: &self.config.access_key,
+                secret_key: &self.config.secret_key,
+                region: &self.config.region,
+            },
+        );
+        let mut request = self
+            .http
+            .request(method, url)
+            .header("authorization", authorization)
+            .body(body);
+        for (name, value) in signed.iter().filter(|(name, _)| name != "host") {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| Error::other(err.to_string()))?;
+        check(response).await
+    }
+
+    async fn xml<T: for<'de> Deserialize<'de>>(response: Response) -> tokio::io::Result<T> {
+        let body = response.text().await.map_err(invalid_data)?;
+        quick_xml::de::from_str(&body).map_err(invalid_data)
+    }
+
+    pub async fn head(&self, key: &str) -> tokio::io::Result<Object> {
+        let response = self.send(Method::HEAD, key, &[], &[], Vec::new()).await?;
+        let headers: &HeaderMap = response.headers();
+        let size = headers
+            .get("content-length")
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(0);
+        let modified = headers
+            .get("last-modified")
+            .and_then(|x| x.to_str().ok())
+            .map(parse_time)
+            .unwrap_or(std::time::UNIX_EPOCH);
+        Ok(Object {
+            key: key.to_string(),
+            size,
+            modified,
+        })
+    }
+
+    /// Fetch an object, skipping the first `offset` bytes.
+    pub async fn get(&self, key: &str, offset: u64) -> tokio::io::Result<Response> {
+        let range = [("range", format!("bytes={}-", offset))];
+        let headers: &[(&str, String)] = if offset > 0 { &range } else { &[] };
+        self.send(Method::GET, key, &[], headers, Vec::new()).await
+    }
+
+    pub async fn put(&self, key: &str, body: Vec<u8>) -> tokio::io::Result<()> {
+        self.send(Method::PUT, key, &[], &[], body).await?;
+        Ok(())
+    }
+
+    pub async fn copy(&self, from: &str, to: &str) -> tokio::io::Result<()> {
+        let source = format!(
+            "/{}/{}",
+            uri_encode(&self.config.bucket, true),
+            uri_encode(from, false)
+        );
+        self.send(
+            Method::PUT,
+            to,
+            &[],
+            &[("x-amz-copy-source", source)],
+            Vec::new(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, key: &str) -> tokio::io::Result<()> {
+        self.send(Method::DELETE, key, &[], &[], Vec::new()).await?;
+        Ok(())
+    }
+
+    /// List keys under `prefix`. With `delimited`, keys below the next `/`
+    /// are folded into common prefixes. Stops after `limit` keys if given.
+    pub async fn list(
+        &self,
+        prefix: &str,
+        delimited: bool,
+        limit: Option<usize>,
+    ) -> tokio::io::Result<Listing> {
+        let mut listing = Listing::default();
+        let mut token = None;
+        loop {
+            let mut query = vec![
+                ("list-type", String::from("2")),
+                ("prefix", prefix.to_string()),
+            ];
+            if delimited {
+                query.push(("delimiter", String::from("/")));
+            }
+            if let Some(limit) = limit {
+                query.push(("max-keys", limit.to_string()));
+            }
+            if let Some(token) = token.take() {
+                query.push(("continuation-token", token));
+            }
+            let response = self.send(Method::GET, "", &query, &[], Vec::new()).await?;
+            let result: ListBucketResult = Self::xml(response).await?;
+            listing
+                .objects
+                .extend(result.contents.into_iter().map(|object| Object {
+                    modified: parse_time(&object.last_modified),
+                    key: object.key,
+                    size: object.size,
+                }));
+            listing
+                .prefixes
+                .extend(result.common_prefixes.into_iter().map(|x| x.prefix));
+            let enough = limit
+                .map(|limit| listing.objects.len() + listing.prefixes.len() >= limit)
+                .unwrap_or(false);
+            match result.next_continuation_token {
+                Some(next) if result.is_truncated && !enough => token = Some(next),
+                _ => return Ok(listing),
+            }
+        }
+    }
+
+    pub async fn create_multipart(&self, key: &str) -> tokio::io::Result<String> {
+        let query = [("uploads", String::new())];
+        let response = self
+            .send(Method::POST, key, &query, &[], Vec::new())
+            .await?;
+        let result: InitiateMultipartUploadResult = Self::xml(response).await?;
+        Ok(result.upload_id)
+    }
+
+    /// Upload one part and return its ETag.
+    pub async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: usize,
+        body: Vec<u8>,
+    ) -> tokio::io::Result<String> {
+        let query = [
+            ("partNumber", number.to_string()),
+            ("uploadId", upload_id.to_string()),
+        ];
+        let response = self.send(Method::PUT, key, &query, &[], body).await?;
+        response
+            .headers()
+            .get("etag")
+            .and_then(|x| x.to_str().ok())
+            .map(String::from)
+            .ok_or_else(|| invalid_data("UploadPart reply without ETag"))
+    }
+
+    pub async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        etags: &[String],
+    ) -> tokio::io::Result<()> {
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (number, etag) in etags.iter().enumerate() {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                number + 1,
+                etag.replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('"', "&quot;")
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let query = [("uploadId", upload_id.to_string())];
+        let response = self
+            .send(Method::POST, key, &query, &[], body.into_bytes())
+            .await?;
+        // S3 may report a failure in the body of a 200 reply.
+        let body = response.text().await.map_err(invalid_data)?;
+        if body.contains("<Error>") {
+            return Err(Error::other(format!(
+                "CompleteMultipartUpload failed: {}",
+                body
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn abort_multipart(&self, key: &str, upload_id: &str) -> tokio::io::Result<()> {
+        let query = [("uploadId", upload_id.to_string())];
+        self.send(Method::DELETE, key, &query, &[], Vec::new())
+            .await?;
+        Ok(())
+    }
+}
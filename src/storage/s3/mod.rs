@@ -0,0 +1,354 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Serves objects in an S3-compatible bucket. A path maps to the object key
+//! of the same name, and directories are key prefixes ending with `/`.
+//! Empty directories are kept as zero-length objects named like the prefix.
+
+mod client;
+mod signature;
+
+use super::{DirEntry, Metadata, ReadStream, StorageBackend, WriteStream};
+use crate::utils::{config::S3Config, fs::resolve};
+use async_trait::async_trait;
+use client::Client;
+use futures_util::TryStreamExt;
+use libc::{S_IFDIR, S_IFREG};
+use std::{
+    future::Future,
+    io::{Error, ErrorKind},
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::SystemTime,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWrite, DuplexStream},
+    sync::oneshot,
+    task::JoinHandle,
+};
+use tokio_util::io::StreamReader;
+
+pub struct S3Storage {
+    client: Arc<Client>,
+    prefix: String,
+    part_size: usize,
+}
+
+fn file_metadata(size: u64, modified: SystemTime) -> Metadata {
+    Metadata {
+        mode: S_IFREG | 0o644,
+        nlink: 1,
+        owner: String::from("ftp"),
+        group: String::from("ftp"),
+        size,
+        modified,
+    }
+}
+
+fn dir_metadata() -> Metadata {
+    Metadata {
+        mode: S_IFDIR | 0o755,
+        nlink: 2,
+        owner: String::from("ftp"),
+        group: String::from("ftp"),
+        size: 0,
+        modified: std::time::UNIX_EPOCH,
+    }
+}
+
+impl S3Storage {
+    pub fn new(config: S3Config) -> tokio::io::Result<Self> {
+        let mut prefix = config.prefix.trim_matches('/').to_string();
+        if !prefix.is_empty() {
+            prefix.push('/');
+        }
+        let part_size = config.part_size;
+        Ok(Self {
+            client: Arc::new(Client::new(config)?),
+            prefix,
+            part_size,
+        })
+    }
+
+    fn key(&self, path: &Path) -> String {
+        let path = resolve("/", path);
+        let path = path.to_string_lossy();
+        format!("{}{}", self.prefix, path.trim_start_matches('/'))
+    }
+
+    /// The prefix holding everything inside the directory `path`.
+    fn dir_key(&self, path: &Path) -> String {
+        let key = self.key(path);
+        if key.is_empty() || key.ends_with('/') {
+            key
+        } else {
+            key + "/"
+        }
+    }
+
+    fn is_root(&self, path: &Path) -> bool {
+        resolve("/", path) == Path::new("/")
+    }
+
+    async fn is_dir(&self, path: &Path) -> tokio::io::Result<bool> {
+        if self.is_root(path) {
+            return Ok(true);
+        }
+        let listing = self
+            .client
+            .list(&self.dir_key(path), false, Some(1))
+            .await?;
+        Ok(!listing.objects.is_empty())
+    }
+
+    async fn check_parent(&self, path: &Path) -> tokio::io::Result<()> {
+        let parent = resolve("/", path);
+        let parent = parent.parent().unwrap_or_else(|| Path::new("/"));
+        if self.is_dir(parent).await? {
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::NotFound))
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Storage {
+    async fn stat(&self, path: &Path) -> tokio::io::Result<Metadata> {
+        if self.is_root(path) {
+            return Ok(dir_metadata());
+        }
+        match self.client.head(&self.key(path)).await {
+            Ok(object) => Ok(file_metadata(object.size, object.modified)),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                if self.is_dir(path).await? {
+                    Ok(dir_metadata())
+                } else {
+                    Err(err)
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn list(&self, path: &Path) -> tokio::io::Result<Vec<DirEntry>> {
+        let prefix = self.dir_key(path);
+        let listing = self.client.list(&prefix, true, None).await?;
+        if listing.objects.is_empty() && listing.prefixes.is_empty() && !self.is_root(path) {
+            return Err(Error::from(ErrorKind::NotFound));
+        }
+        let mut entries = Vec::new();
+        for object in listing.objects {
+            let name = &object.key[prefix.len()..];
+            if !name.is_empty() {
+                entries.push(DirEntry {
+                    name: name.to_string(),
+                    metadata: file_metadata(object.size, object.modified),
+                });
+            }
+        }
+        for dir in listing.prefixes {
+            let name = dir[prefix.len()..].trim_end_matches('/');
+            if !name.is_empty() {
+                entries.push(DirEntry {
+                    name: name.to_string(),
+                    metadata: dir_metadata(),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    async fn open_read(&self, path: &Path, offset: u64) -> tokio::io::Result<ReadStream> {
+        if self.is_root(path) {
+            return Err(Error::from(ErrorKind::IsADirectory));
+        }
+        let response = self.client.get(&self.key(path), offset).await?;
+        let stream = response
+            .bytes_stream()
+            .map_err(|err| Error::other(err.to_string()));
+        Ok(Box::new(StreamReader::new(stream)))
+    }
+
+    async fn open_write(&self, path: &Path, offset: u64) -> tokio::io::Result<WriteStream> {
+        if offset > 0 {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "S3 objects can't be written at an offset",
+            ));
+        }
+        if self.is_root(path) || self.is_dir(path).await? {
+            return Err(Error::from(ErrorKind::IsADirectory));
+        }
+        self.check_parent(path).await?;
+        let (pipe, reader) = tokio::io::duplex(64 << 10);
+        let (commit, committed) = oneshot::channel();
+        let upload = tokio::spawn(upload(
+            self.client.clone(),
+            self.key(path),
+            self.part_size,
+            reader,
+            committed,
+        ));
+        Ok(Box::new(S3Writer {
+            pipe,
+            commit: Some(commit),
+            upload,
+        }))
+    }
+
+    async fn mkdir(&self, path: &Path) -> tokio::io::Result<()> {
+        if self.stat(path).await.is_ok() {
+            return Err(Error::from(ErrorKind::AlreadyExists));
+        }
+        self.check_parent(path).await?;
+        self.client.put(&self.dir_key(path), Vec::new()).await
+    }
+
+    async fn remove(&self, path: &Path) -> tokio::io::Result<()> {
+        if self.stat(path).await?.is_dir() {
+            let prefix = self.dir_key(path);
+            let listing = self.client.list(&prefix, false, Some(2)).await?;
+            if listing.objects.iter().any(|object| object.key != prefix) {
+                return Err(Error::from(ErrorKind::DirectoryNotEmpty));
+            }
+            self.client.delete(&prefix).await
+        } else {
+            self.client.delete(&self.key(path)).await
+        }
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> tokio::io::Result<()> {
+        let source = self.stat(from).await?;
+        if resolve("/", to).starts_with(resolve("/", from)) {
+            return Err(Error::from(ErrorKind::InvalidInput));
+        }
+        match self.stat(to).await {
+            Ok(target) if source.is_dir() || target.is_dir() => {
+                return Err(Error::from(ErrorKind::AlreadyExists))
+            }
+            Ok(_) => {}
+            Err(_) => self.check_parent(to).await?,
+        }
+        if !source.is_dir() {
+            self.client.copy(&self.key(from), &self.key(to)).await?;
+            return self.client.delete(&self.key(from)).await;
+        }
+        let (from, to) = (self.dir_key(from), self.dir_key(to));
+        for object in self.client.list(&from, false, None).await?.objects {
+            let target = format!("{}{}", to, &object.key[from.len()..]);
+            self.client.copy(&object.key, &target).await?;
+            self.client.delete(&object.key).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Upload what arrives through `pipe` once `committed` fires. Small files go
+/// up in a single PUT, larger ones as a multipart upload that is aborted if
+/// anything goes wrong.
+async fn upload(
+    client: Arc<Client>,
+    key: String,
+    part_size: usize,
+    pipe: DuplexStream,
+    committed: oneshot::Receiver<()>,
+) -> tokio::io::Result<()> {
+    let mut upload_id = None;
+    let result = upload_parts(&client, &key, part_size, pipe, committed, &mut upload_id).await;
+    if let (Err(_), Some(upload_id)) = (&result, &upload_id) {
+        let _ = client.abort_multipart(&key, upload_id).await;
+    }
+    result
+}
+
+async fn upload_parts(
+    client: &Client,
+    key: &str,
+    part_size: usize,
+    mut pipe: DuplexStream,
+    committed: oneshot::Receiver<()>,
+    upload_id: &mut Option<String>,
+) -> tokio::io::Result<()> {
+    let aborted = || Error::new(ErrorKind::Interrupted, "upload aborted");
+    let mut etags = Vec::new();
+    loop {
+        let mut part = Vec::with_capacity(part_size);
+        (&mut pipe)
+            .take(part_size as u64)
+            .read_to_end(&mut part)
+            .await?;
+        let last = part.len() < part_size;
+        if last && upload_id.is_none() {
+            committed.await.map_err(|_| aborted())?;
+            return client.put(key, part).await;
+        }
+        let id = match upload_id {
+            Some(id) => id.clone(),
+            None => upload_id
+                .insert(client.create_multipart(key).await?)
+                .clone(),
+        };
+        if !part.is_empty() {
+            etags.push(client.upload_part(key, &id, etags.len() + 1, part).await?);
+        }
+        if last {
+            committed.await.map_err(|_| aborted())?;
+            return client.complete_multipart(key, &id, &etags).await;
+        }
+    }
+}
+
+/// Feeds the upload task. Nothing is stored unless the stream is shut down
+/// cleanly; dropping it half way aborts the upload.
+struct S3Writer {
+    pipe: DuplexStream,
+    commit: Option<oneshot::Sender<()>>,
+    upload: JoinHandle<tokio::io::Result<()>>,
+}
+
+impl S3Writer {
+    /// Report why the upload task stopped, if it did.
+    fn upload_error(&mut self, cx: &mut Context<'_>, err: Error) -> Error {
+        match Pin::new(&mut self.upload).poll(cx) {
+            Poll::Ready(Ok(Err(upload_err))) => upload_err,
+            _ => err,
+        }
+    }
+}
+
+impl AsyncWrite for S3Writer {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<tokio::io::Result<usize>> {
+        match Pin::new(&mut self.pipe).poll_write(cx, buf) {
+            Poll::Ready(Err(err)) => Poll::Ready(Err(self.upload_error(cx, err))),
+            poll => poll,
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<tokio::io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<tokio::io::Result<()>> {
+        if let Err(err) = ready!(Pin::new(&mut self.pipe).poll_shutdown(cx)) {
+            return Poll::Ready(Err(self.upload_error(cx, err)));
+        }
+        if let Some(commit) = self.commit.take() {
+            let _ = commit.send(());
+        }
+        match Pin::new(&mut self.upload).poll(cx) {
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(Error::other(err))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
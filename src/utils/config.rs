@@ -2,34 +2,791 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
 use std::{
+    collections::BTreeMap,
     net::{IpAddr, SocketAddr},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
-#[derive(Serialize, Deserialize)]
+/// Environment variables starting with this override settings, such as
+/// `KIRAFTP_PORT`. Nested settings are separated by a double underscore, as
+/// in `KIRAFTP_STORAGE__BUCKET`.
+pub const ENV_PREFIX: &str = "KIRAFTP_";
+
+/// Server settings, usually read from `config.yaml`. Credentials and the
+/// served directory have no defaults and must be given.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default = "default_listen")]
     pub listen: IpAddr,
+    #[serde(default = "default_port")]
     pub port: u16,
     pub username: String,
     pub password: String,
     pub path: PathBuf,
+    /// Settings for particular users, by login name. Those for `anonymous`
+    /// apply to anonymous logins.
+    #[serde(default)]
+    pub users: BTreeMap<String, UserConfig>,
+    #[serde(default)]
+    pub storage: Storage,
+    /// Seconds to let running transfers finish when shutting down.
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Reload the config file whenever it changes, besides on SIGHUP.
+    #[serde(default)]
+    pub watch_config: bool,
+    #[serde(default)]
+    pub log: LogConfig,
+    /// Which local port active mode data connections come from.
+    #[serde(default)]
+    pub active_mode: ActiveMode,
+    /// Only take passive data connections from the client's own address,
+    /// turning others away, unless the user is allowed FXP.
+    #[serde(default = "default_check_passive_peer")]
+    pub check_passive_peer: bool,
+    /// Ports to take passive mode data connections on, rather than any free
+    /// port. Sessions share them, each port serving one at a time.
+    #[serde(default)]
+    pub passive_ports: Option<PortRange>,
+    /// Most passive mode ports open at once, across all sessions.
+    #[serde(default)]
+    pub max_passive_ports: Option<usize>,
+    /// Write each STOR to a hidden file next to its destination, renamed
+    /// into place once complete, so that nobody sees a partial file.
+    #[serde(default)]
+    pub atomic_uploads: bool,
+    /// With atomic uploads, move what arrived of a failed STOR of a new file
+    /// into place anyway. The client resumes it by sending REST with the
+    /// size of the partial file, then STOR again. A failed STOR replacing a
+    /// file is always dropped, leaving the old file whole.
+    #[serde(default)]
+    pub keep_partial_uploads: bool,
+    /// Seconds a passive mode port waits for its data connection, whether
+    /// or not a transfer was asked for, before it is closed.
+    #[serde(default = "default_passive_timeout")]
+    pub passive_timeout: u64,
+    /// Append a line in wu-ftpd xferlog format to this file for each RETR
+    /// and STOR, whether it completed or not.
+    #[serde(default)]
+    pub xferlog: Option<PathBuf>,
+    /// Serve Prometheus metrics over HTTP at `/metrics` on this address.
+    #[serde(default)]
+    pub metrics: Option<SocketAddr>,
+    /// Record logins and changes to files as JSON lines.
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// Names that STOR, APPE, RNTO and MKD may create, unless a user has
+    /// their own rules.
+    #[serde(default)]
+    pub upload_filter: FilterConfig,
+    /// Largest file, in bytes, that uploads may leave, unless a user has
+    /// their own limit. Appending counts what the file already holds.
+    #[serde(default)]
+    pub max_upload_size: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    /// Lowest level logged: critical, error, warn, info, debug or trace.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    #[serde(default)]
+    pub output: LogOutput,
+}
+
+/// Where log records go.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum LogOutput {
+    /// Human readable lines on stderr.
+    #[default]
+    Terminal,
+    /// One JSON object per line, appended to `path`.
+    Json { path: PathBuf },
+    /// The local syslog daemon, through `/dev/log`.
+    Syslog {
+        #[serde(default = "default_facility")]
+        facility: String,
+    },
+}
+
+/// What to run once files have been changed.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HooksConfig {
+    /// Seconds a hook may take before it is given up on. Commands are
+    /// killed.
+    #[serde(default = "default_hook_timeout")]
+    pub timeout: u64,
+    /// Most hooks running at once, across all sessions. Others wait.
+    #[serde(default = "default_max_running_hooks")]
+    pub max_running: usize,
+    /// Most hooks waiting for their turn. Hooks that would wait past it are
+    /// dropped, with a warning.
+    #[serde(default = "default_max_queued_hooks")]
+    pub max_queued: usize,
+    #[serde(default)]
+    pub run: Vec<Hook>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Hook {
+    /// Run `command` with `args`. The event is described by the environment
+    /// variables `KIRAFTP_EVENT`, `KIRAFTP_PATH`, `KIRAFTP_FROM` (for
+    /// renames), `KIRAFTP_USER`, `KIRAFTP_PEER` and `KIRAFTP_SIZE`.
+    ///
+    /// Paths are those seen by the client. With local storage,
+    /// `KIRAFTP_REAL_PATH` and `KIRAFTP_REAL_FROM` also give where the files
+    /// are on disk. They are left out for memory and S3 storage.
+    Command {
+        command: PathBuf,
+        #[serde(default)]
+        args: Vec<String>,
+        /// Events to run for, or all of them when empty.
+        #[serde(default)]
+        events: Vec<HookEvent>,
+    },
+    /// POST the event as a JSON object to `url`, an `http://` URL. Its fields
+    /// match the variables given to commands, with `real_path` and
+    /// `real_from` null when not known.
+    Webhook {
+        url: String,
+        #[serde(default)]
+        events: Vec<HookEvent>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HookEvent {
+    /// A STOR completed.
+    Upload,
+    /// An APPE completed.
+    Append,
+    Rename,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Entries are appended to this file.
+    pub path: PathBuf,
+    /// Chain entries by hash, so that tampering can be detected.
+    #[serde(default)]
+    pub hash_chain: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    /// Allow data connections with hosts other than the client, so that
+    /// files can be moved between servers (FXP). Off by default, since it
+    /// also lets the client aim the server at arbitrary hosts.
+    #[serde(default)]
+    pub allow_fxp: bool,
+    /// Directory of the served storage that the user sees as `/`, rather
+    /// than the whole of it. It is created at login if missing.
+    #[serde(default)]
+    pub home: Option<PathBuf>,
+    /// Most the user may store under their home, which must be set and
+    /// must not hold the home of another user. Files put there by users who
+    /// can see it count too.
+    #[serde(default)]
+    pub quota: Option<QuotaConfig>,
+    /// Rules for the names the user may create, in place of the global
+    /// `upload_filter`.
+    #[serde(default)]
+    pub upload_filter: Option<FilterConfig>,
+    /// Largest file the user may upload, in place of the global
+    /// `max_upload_size`.
+    #[serde(default)]
+    pub max_upload_size: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaConfig {
+    #[serde(default)]
+    pub bytes: Option<u64>,
+    #[serde(default)]
+    pub files: Option<u64>,
+}
+
+/// Rules on the names of uploaded files and created directories. Only the
+/// last component of a path is checked.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FilterConfig {
+    /// When not empty, names must match one of these.
+    #[serde(default)]
+    pub allow: Vec<NamePattern>,
+    /// Names matching any of these are refused, even if allowed.
+    #[serde(default)]
+    pub deny: Vec<NamePattern>,
+    /// Longest name accepted, in characters.
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    /// Refuse names starting with a dot.
+    #[serde(default)]
+    pub deny_dotfiles: bool,
+    /// Refuse names holding control characters, such as newlines.
+    #[serde(default)]
+    pub deny_control_characters: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum NamePattern {
+    /// A shell pattern such as `*.exe`, ignoring case.
+    Glob(String),
+    /// A regular expression, found anywhere in the name unless anchored.
+    Regex(String),
+    /// An extension such as `exe`, without the dot, ignoring case.
+    Extension(String),
+}
+
+/// An inclusive range of ports.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PortRange {
+    pub min: u16,
+    pub max: u16,
+}
+
+/// How the source port of active mode data connections is chosen.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ActiveMode {
+    /// Any free port, as chosen by the system.
+    #[default]
+    Ephemeral,
+    /// The port just below the control port, as RFC 959 has it: port 20
+    /// when serving on 21, which needs privileges to bind.
+    Strict,
+}
+
+/// Where served files are kept.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Storage {
+    /// The directory given by `path`.
+    #[default]
+    Local,
+    /// Memory only, optionally limited to `quota` bytes. `path` is ignored.
+    Memory {
+        #[serde(default)]
+        quota: Option<u64>,
+    },
+    /// An S3-compatible bucket. `path` is ignored.
+    S3(S3Config),
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct S3Config {
+    /// Base URL of the service, such as `https://s3.us-east-1.amazonaws.com`.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prepended to every object key, so that a bucket can be shared.
+    #[serde(default)]
+    pub prefix: String,
+    /// Address the bucket as `endpoint/bucket` rather than `bucket.endpoint`.
+    #[serde(default = "default_path_style")]
+    pub path_style: bool,
+    /// Size of each part in multipart uploads. S3 requires at least 5 MiB.
+    #[serde(default = "default_part_size")]
+    pub part_size: usize,
+}
+
+fn default_listen() -> IpAddr {
+    IpAddr::from([0, 0, 0, 0])
+}
+
+fn default_port() -> u16 {
+    21
+}
+
+fn default_log_level() -> String {
+    String::from("info")
+}
+
+fn default_facility() -> String {
+    String::from("daemon")
+}
+
+fn default_check_passive_peer() -> bool {
+    true
+}
+
+fn default_passive_timeout() -> u64 {
+    60
+}
+
+fn default_hook_timeout() -> u64 {
+    30
+}
+
+fn default_max_running_hooks() -> usize {
+    4
+}
+
+fn default_max_queued_hooks() -> usize {
+    64
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_region() -> String {
+    String::from("us-east-1")
+}
+
+fn default_path_style() -> bool {
+    true
+}
+
+fn default_part_size() -> usize {
+    8 << 20
 }
 
 impl Config {
+    /// Assemble a config from the defaults, then `file` (YAML, or TOML when
+    /// it ends in `.toml`), then the `KIRAFTP_*` entries of `vars`, later
+    /// sources overriding earlier ones. Entries naming no setting, such as
+    /// `KIRAFTP_CONFIG`, are left out; see [`Config::unknown_vars`].
+    ///
+    /// Names are lowercased, except that a user already in `file` is found
+    /// whatever the case: `KIRAFTP_USERS__ALICE__HOME` sets the home of
+    /// `Alice` if the file has that user, and of `alice` otherwise.
+    ///
+    /// A secret such as `password` may instead be given as `password_file`,
+    /// the path of a file holding it.
+    pub fn load(
+        file: Option<&Path>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Config, String> {
+        let mut layered = match file {
+            Some(path) => read_file(path).map_err(|err| format!("{}: {}", path.display(), err))?,
+            None => Mapping::new(),
+        };
+        let settings = settings();
+        for (name, value) in vars {
+            let mut key = match env_key(&name) {
+                Some(key) if is_setting(&settings, &key) => key,
+                _ => continue,
+            };
+            // Users from the file keep the case they were given there.
+            if key.len() > 1 && key[0] == "users" {
+                if let Some(Value::Mapping(users)) = layered.get(&Value::from("users")) {
+                    let found = users
+                        .iter()
+                        .filter_map(|(x, _)| x.as_str())
+                        .find(|x| x.to_lowercase() == key[1]);
+                    if let Some(found) = found {
+                        key[1] = found.to_string();
+                    }
+                }
+            }
+            set(&mut layered, &key, env_value(&value));
+        }
+        read_secret_files(&mut layered)?;
+        // Going through YAML text lets plain numbers fill string settings.
+        let text = serde_yaml::to_string(&layered).map_err(|err| err.to_string())?;
+        serde_yaml::from_str(&text).map_err(|err| err.to_string())
+    }
+
+    /// The `KIRAFTP_*` entries of `vars` that name no setting, and so are
+    /// left out by [`Config::load`].
+    pub fn unknown_vars(vars: impl IntoIterator<Item = (String, String)>) -> Vec<String> {
+        let settings = settings();
+        vars.into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| env_key(name).is_some_and(|key| !is_setting(&settings, &key)))
+            .collect()
+    }
+
+    /// The config as YAML, with secrets replaced by `(redacted)`.
+    pub fn redacted(&self) -> String {
+        let mut value = serde_yaml::to_value(self).unwrap_or(Value::Null);
+        redact(&mut value);
+        serde_yaml::to_string(&value)
+            .unwrap_or_default()
+            .trim_start_matches("---\n")
+            .to_string()
+    }
+
+    /// Settings for the user logged in as `name`.
+    pub fn user(&self, name: &str) -> UserConfig {
+        self.users.get(name).cloned().unwrap_or_default()
+    }
+
     pub fn address(&self) -> SocketAddr {
         SocketAddr::new(self.listen, self.port)
     }
+
+    /// Check the settings that parsing alone cannot, returning every problem
+    /// found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        if self.port == 0 {
+            problems.push(String::from("port: must be between 1 and 65535"));
+        }
+        if self.username.is_empty() {
+            problems.push(String::from("username: must not be empty"));
+        }
+        if self.password.is_empty() {
+            problems.push(String::from("password: must not be empty"));
+        }
+        if let Some(range) = &self.passive_ports {
+            if range.min == 0 || range.min > range.max {
+                problems.push(String::from("passive_ports: min must be between 1 and max"));
+            }
+        }
+        if self.hooks.max_running == 0 {
+            problems.push(String::from("hooks.max_running: must be at least 1"));
+        }
+        for hook in &self.hooks.run {
+            if let Hook::Webhook { url, .. } = hook {
+                if !url.starts_with("http://") {
+                    problems.push(format!("hooks.run: {} is not an http:// URL", url));
+                }
+            }
+        }
+        for (name, user) in &self.users {
+            if user.quota.is_none() {
+                continue;
+            }
+            let home = match &user.home {
+                Some(home) => crate::utils::fs::resolve("/", home),
+                None => {
+                    problems.push(format!("users.{}.quota: needs a home", name));
+                    continue;
+                }
+            };
+            // Users that are not configured see the whole storage.
+            let logins = [self.username.as_str(), "anonymous"];
+            let others = self.users.keys().map(String::as_str).chain(logins);
+            for other in others.filter(|x| *x != name) {
+                let other_home = match self.users.get(other).and_then(|x| x.home.as_ref()) {
+                    Some(other_home) => crate::utils::fs::resolve("/", other_home),
+                    None => PathBuf::from("/"),
+                };
+                if other_home.starts_with(&home) {
+                    problems.push(format!(
+                        "users.{}.home: holds the home of {}, whose files would count",
+                        name, other
+                    ));
+                    break;
+                }
+            }
+        }
+        let filters = std::iter::once((String::from("upload_filter"), &self.upload_filter)).chain(
+            self.users.iter().filter_map(|(name, user)| {
+                let filter = user.upload_filter.as_ref()?;
+                Some((format!("users.{}.upload_filter", name), filter))
+            }),
+        );
+        for (key, filter) in filters {
+            if let Err(err) = crate::utils::filter::NameFilter::new(filter) {
+                problems.push(format!("{}: {}", key, err));
+            }
+        }
+        if self.max_passive_ports == Some(0) {
+            problems.push(String::from("max_passive_ports: must be at least 1"));
+        }
+        if self.log.level.parse::<slog::Level>().is_err() {
+            problems.push(format!("log.level: unknown level {}", self.log.level));
+        }
+        if let LogOutput::Syslog { facility } = &self.log.output {
+            if crate::utils::log::facility(facility).is_none() {
+                problems.push(format!("log.facility: unknown facility {}", facility));
+            }
+        }
+        match &self.storage {
+            Storage::Local if self.path.as_os_str().is_empty() => {
+                problems.push(String::from("path: must not be empty"))
+            }
+            Storage::Local => match std::fs::metadata(&self.path) {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => problems.push(format!("path: {} is not a directory", self.path.display())),
+                Err(err) => problems.push(format!("path: {}: {}", self.path.display(), err)),
+            },
+            Storage::Memory { .. } => {}
+            Storage::S3(s3) => {
+                if cfg!(not(feature = "s3")) {
+                    problems.push(String::from(
+                        "storage.type: s3 is not supported by this build",
+                    ));
+                }
+                if !s3.endpoint.starts_with("http://") && !s3.endpoint.starts_with("https://") {
+                    problems.push(format!(
+                        "storage.endpoint: {} is not an http or https URL",
+                        s3.endpoint
+                    ));
+                }
+                if s3.bucket.is_empty() {
+                    problems.push(String::from("storage.bucket: must not be empty"));
+                }
+                if s3.part_size < 5 << 20 {
+                    problems.push(String::from("storage.part_size: must be at least 5 MiB"));
+                }
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Describe each setting that differs in `new`, one line per setting.
+    /// Secrets are not shown.
+    pub fn changes(&self, new: &Config) -> Vec<String> {
+        let mut changes = Vec::new();
+        let old = serde_yaml::to_value(self).unwrap_or(Value::Null);
+        let new = serde_yaml::to_value(new).unwrap_or(Value::Null);
+        diff(String::new(), &old, &new, &mut changes);
+        changes
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    key.ends_with("password") || key.ends_with("secret_key")
+}
+
+fn read_file(path: &Path) -> Result<Mapping, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let value: Value = if path.extension().is_some_and(|x| x == "toml") {
+        toml::from_str(&text).map_err(|err| err.to_string())?
+    } else {
+        serde_yaml::from_str(&text).map_err(|err| err.to_string())?
+    };
+    match value {
+        Value::Mapping(mapping) => Ok(mapping),
+        Value::Null => Ok(Mapping::new()),
+        _ => Err(String::from("expected a table of settings")),
+    }
+}
+
+/// Numbers and booleans are taken as such only when written plainly, so that
+/// a password like `0x10` stays a string.
+fn env_value(text: &str) -> Value {
+    match serde_yaml::from_str(text) {
+        Ok(value @ Value::Number(_)) | Ok(value @ Value::Bool(_))
+            if serde_yaml::to_string(&value)
+                .is_ok_and(|x| x.trim_start_matches("---").trim() == text) =>
+        {
+            value
+        }
+        _ => Value::String(text.to_string()),
+    }
+}
+
+/// Set the setting at `key`, replacing its `_file` counterpart or the other
+/// way round.
+/// The setting named by environment variable `name`, lowercased, if it has
+/// the prefix.
+fn env_key(name: &str) -> Option<Vec<String>> {
+    let key = name.strip_prefix(ENV_PREFIX)?;
+    Some(key.split("__").map(|x| x.to_lowercase()).collect())
+}
+
+/// The names of the top level settings.
+fn settings() -> Vec<String> {
+    match serde_yaml::to_value(Config::default()) {
+        Ok(Value::Mapping(settings)) => settings
+            .iter()
+            .filter_map(|(x, _)| x.as_str())
+            .map(String::from)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether `key` is under one of the top level `settings`, or gives the file
+/// holding a secret one.
+fn is_setting(settings: &[String], key: &[String]) -> bool {
+    let top = key[0].as_str();
+    let top = top
+        .strip_suffix("_file")
+        .filter(|x| is_secret(x))
+        .unwrap_or(top);
+    settings.iter().any(|x| x == top)
+}
+
+fn set(mapping: &mut Mapping, key: &[String], value: Value) {
+    match key {
+        [] => {}
+        [name] => {
+            let other = match name.strip_suffix("_file") {
+                Some(stem) => stem.to_string(),
+                None => format!("{}_file", name),
+            };
+            mapping.remove(&Value::from(other));
+            mapping.insert(Value::from(name.as_str()), value);
+        }
+        [name, rest @ ..] => {
+            let name = Value::from(name.as_str());
+            if !matches!(mapping.get(&name), Some(Value::Mapping(_))) {
+                mapping.insert(name.clone(), Value::Mapping(Mapping::new()));
+            }
+            if let Some(Value::Mapping(inner)) = mapping.get_mut(&name) {
+                set(inner, rest, value);
+            }
+        }
+    }
+}
+
+fn read_secret_files(mapping: &mut Mapping) -> Result<(), String> {
+    let files: Vec<(String, Value)> = mapping
+        .iter()
+        .filter_map(|(key, value)| {
+            let stem = key.as_str()?.strip_suffix("_file")?;
+            Some((stem.to_string(), value.clone())).filter(|x| is_secret(&x.0))
+        })
+        .collect();
+    for (stem, path) in files {
+        let key = Value::from(stem.as_str());
+        if mapping.contains_key(&key) {
+            return Err(format!("{} and {}_file are both set", stem, stem));
+        }
+        let path = path
+            .as_str()
+            .ok_or_else(|| format!("{}_file: expected a path", stem))?;
+        let secret = std::fs::read_to_string(path)
+            .map_err(|err| format!("{}_file: {}: {}", stem, path, err))?;
+        let secret = secret.strip_suffix('\n').unwrap_or(&secret);
+        let secret = secret.strip_suffix('\r').unwrap_or(secret);
+        mapping.remove(&Value::from(format!("{}_file", stem)));
+        mapping.insert(key, Value::from(secret));
+    }
+    for (_, value) in mapping.iter_mut() {
+        if let Value::Mapping(inner) = value {
+            read_secret_files(inner)?;
+        }
+    }
+    Ok(())
+}
+
+fn redact(value: &mut Value) {
+    if let Value::Mapping(mapping) = value {
+        for (key, value) in mapping.iter_mut() {
+            if key.as_str().is_some_and(is_secret) {
+                *value = Value::from("(redacted)");
+            } else {
+                redact(value);
+            }
+        }
+    }
+}
+
+fn show(key: &str, value: Option<&Value>) -> String {
+    match value {
+        None => String::from("(unset)"),
+        Some(_) if is_secret(key) => String::from("(redacted)"),
+        Some(value) => serde_yaml::to_string(value)
+            .unwrap_or_default()
+            .trim_start_matches("---")
+            .trim()
+            .replace('\n', ", "),
+    }
 }
 
+fn diff(key: String, old: &Value, new: &Value, changes: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Mapping(old), Value::Mapping(new)) => {
+            let mut keys: Vec<&Value> = old.iter().chain(new.iter()).map(|x| x.0).collect();
+            keys.sort_by_key(|x| x.as_str().unwrap_or_default().to_string());
+            keys.dedup();
+            for name in keys {
+                let name_str = name.as_str().unwrap_or_default();
+                let key = if key.is_empty() {
+                    name_str.to_string()
+                } else {
+                    format!("{}.{}", key, name_str)
+                };
+                match (old.get(name), new.get(name)) {
+                    (Some(old), Some(new)) => diff(key, old, new, changes),
+                    (old, new) => changes.push(format!(
+                        "{}: {} -> {}",
+                        key,
+                        show(&key, old),
+                        show(&key, new)
+                    )),
+                }
+            }
+        }
+        (old, new) if old != new => changes.push(format!(
+            "{}: {} -> {}",
+            key,
+            show(&key, Some(old)),
+            show(&key, Some(new))
+        )),
+        _ => {}
+    }
+}
+
+/// The defaults, with the credentials and the served directory left empty.
+/// They must be filled in before the config passes [`Config::validate`].
 impl Default for Config {
     fn default() -> Self {
         Self {
-            listen: IpAddr::from([0, 0, 0, 0]),
-            port: 21,
-            username: String::from("root"),
-            password: String::from("password"),
-            path: PathBuf::from("/"),
+            listen: default_listen(),
+            port: default_port(),
+            username: String::new(),
+            password: String::new(),
+            path: PathBuf::new(),
+            users: BTreeMap::new(),
+            storage: Storage::default(),
+            shutdown_timeout: default_shutdown_timeout(),
+            watch_config: false,
+            log: LogConfig::default(),
+            active_mode: ActiveMode::default(),
+            check_passive_peer: default_check_passive_peer(),
+            passive_ports: None,
+            max_passive_ports: None,
+            passive_timeout: default_passive_timeout(),
+            atomic_uploads: false,
+            keep_partial_uploads: false,
+            xferlog: None,
+            metrics: None,
+            audit: None,
+            hooks: HooksConfig::default(),
+            upload_filter: FilterConfig::default(),
+            max_upload_size: None,
+        }
+    }
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            timeout: default_hook_timeout(),
+            max_running: default_max_running_hooks(),
+            max_queued: default_max_queued_hooks(),
+            run: Vec::new(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            output: LogOutput::default(),
         }
     }
 }
@@ -0,0 +1,141 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Checks on the names users give to new files and directories.
+
+use crate::utils::config::{FilterConfig, NamePattern};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+
+enum Matcher {
+    Glob(GlobMatcher),
+    Regex(Regex),
+    Extension(String),
+}
+
+impl Matcher {
+    fn new(pattern: &NamePattern) -> Result<Self, String> {
+        Ok(match pattern {
+            NamePattern::Glob(glob) => Matcher::Glob(
+                GlobBuilder::new(glob)
+                    .case_insensitive(true)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|err| err.to_string())?
+                    .compile_matcher(),
+            ),
+            NamePattern::Regex(regex) => {
+                Matcher::Regex(Regex::new(regex).map_err(|err| err.to_string())?)
+            }
+            NamePattern::Extension(extension) => {
+                Matcher::Extension(extension.trim_start_matches('.').to_lowercase())
+            }
+        })
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        match self {
+            Matcher::Glob(glob) => glob.is_match(name),
+            Matcher::Regex(regex) => regex.is_match(name),
+            Matcher::Extension(extension) => name
+                .rsplit_once('.')
+                .is_some_and(|(stem, x)| !stem.is_empty() && x.to_lowercase() == *extension),
+        }
+    }
+}
+
+/// The rules of a [`FilterConfig`], with its patterns compiled.
+pub struct NameFilter {
+    allow: Vec<Matcher>,
+    deny: Vec<Matcher>,
+    max_length: Option<usize>,
+    deny_dotfiles: bool,
+    deny_control_characters: bool,
+}
+
+impl NameFilter {
+    /// Compile the patterns of `config`, failing on the first invalid one.
+    pub fn new(config: &FilterConfig) -> Result<Self, String> {
+        let compile = |patterns: &[NamePattern]| {
+            patterns
+                .iter()
+                .map(Matcher::new)
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            allow: compile(&config.allow)?,
+            deny: compile(&config.deny)?,
+            max_length: config.max_length,
+            deny_dotfiles: config.deny_dotfiles,
+            deny_control_characters: config.deny_control_characters,
+        })
+    }
+
+    /// Check `name`, a single path component, giving the reason it is
+    /// refused if it is.
+    pub fn check(&self, name: &str) -> Result<(), &'static str> {
+        if self
+            .max_length
+            .is_some_and(|max| name.chars().count() > max)
+        {
+            return Err("name is too long");
+        }
+        if self.deny_dotfiles && name.starts_with('.') {
+            return Err("hidden names are not allowed");
+        }
+        if self.deny_control_characters && name.chars().any(char::is_control) {
+            return Err("name has control characters");
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|x| x.is_match(name)) {
+            return Err("name is not allowed");
+        }
+        if self.deny.iter().any(|x| x.is_match(name)) {
+            return Err("name is not allowed");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let config = FilterConfig {
+            allow: vec![
+                NamePattern::Glob(String::from("*.txt")),
+                NamePattern::Regex(String::from("^report-[0-9]+")),
+                NamePattern::Extension(String::from("EXE")),
+            ],
+            deny: vec![NamePattern::Regex(String::from("secret"))],
+            ..FilterConfig::default()
+        };
+        let filter = NameFilter::new(&config).unwrap();
+        assert!(filter.check("notes.TXT").is_ok());
+        assert!(filter.check("report-1.pdf").is_ok());
+        assert!(filter.check("setup.exe").is_ok());
+        assert!(filter.check("notes.md").is_err());
+        assert!(filter.check("exe").is_err());
+        assert!(filter.check("secret.txt").is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let config = FilterConfig {
+            max_length: Some(8),
+            deny_dotfiles: true,
+            deny_control_characters: true,
+            ..FilterConfig::default()
+        };
+        let filter = NameFilter::new(&config).unwrap();
+        assert!(filter.check("été.txt").is_ok());
+        assert!(filter.check("too-long.txt").is_err());
+        assert!(filter.check(".profile").is_err());
+        assert!(filter.check("a\tb").is_err());
+        assert!(NameFilter::new(&FilterConfig::default())
+            .unwrap()
+            .check(".a\n")
+            .is_ok());
+    }
+}
@@ -1,46 +1,45 @@
 // Copyright 2021 Slowy <slowyfine@gmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
+use crate::storage::DirEntry;
 use chrono::{DateTime, Local};
 use libc::*;
-use std::{os::unix::prelude::*, path::Path};
-use tokio::fs::{self as tokiofs, DirEntry};
+use std::path::{Component, Path, PathBuf};
 
-#[inline(always)]
-pub async fn is_dir(path: impl AsRef<Path>) -> bool {
-    tokiofs::metadata(path)
-        .await
-        .map(|meta| meta.is_dir())
-        .unwrap_or(false)
+/// Resolve `path` against the virtual directory `base` without touching the
+/// filesystem. `..` never climbs above `/`, so the result stays inside the
+/// served directory.
+pub fn resolve(base: impl AsRef<Path>, path: impl AsRef<Path>) -> PathBuf {
+    let mut resolved = PathBuf::from("/");
+    for component in base.as_ref().join(path).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            _ => {}
+        }
+    }
+    resolved
 }
 
-pub async fn display(item: &DirEntry) -> Option<String> {
-    match item.metadata().await {
-        Ok(metadata) => {
-            let mode = parse_permissions(metadata.permissions().mode());
-            let nlink = metadata.nlink();
-            let user = users::get_user_by_uid(metadata.uid())?;
-            let user = user.name().to_string_lossy();
-            let group = users::get_group_by_gid(metadata.gid())?;
-            let group = group.name().to_string_lossy();
-            let size = metadata.size();
-            let modified = DateTime::<Local>::from(metadata.modified().unwrap())
-                .format("%b %d %H:%M")
-                .to_string();
-            let filename = item.file_name().into_string().unwrap();
-            Some(if filename.contains(' ') {
-                format!(
-                    "{} {} {} {} {} {} '{}'\r\n",
-                    mode, nlink, user, group, size, modified, filename
-                )
-            } else {
-                format!(
-                    "{} {} {} {} {} {} {}\r\n",
-                    mode, nlink, user, group, size, modified, filename
-                )
-            })
-        }
-        Err(_) => None,
+pub fn display(item: &DirEntry) -> String {
+    let metadata = &item.metadata;
+    let mode = parse_permissions(metadata.mode);
+    let modified = DateTime::<Local>::from(metadata.modified)
+        .format("%b %d %H:%M")
+        .to_string();
+    let filename = &item.name;
+    if filename.contains(' ') {
+        format!(
+            "{} {} {} {} {} {} '{}'\r\n",
+            mode, metadata.nlink, metadata.owner, metadata.group, metadata.size, modified, filename
+        )
+    } else {
+        format!(
+            "{} {} {} {} {} {} {}\r\n",
+            mode, metadata.nlink, metadata.owner, metadata.group, metadata.size, modified, filename
+        )
     }
 }
 
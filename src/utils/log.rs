@@ -0,0 +1,129 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use crate::utils::config::{LogConfig, LogOutput};
+use slog::{o, Drain, Key, Level, Logger, Never, OwnedKVList, Record, KV};
+use slog_async::{Async, AsyncGuard};
+use slog_json::Json;
+use slog_term::{CompactFormat, TermDecorator};
+use std::{
+    fmt::{self, Write},
+    fs::OpenOptions,
+    io,
+    os::unix::net::UnixDatagram,
+};
+
+/// Build the root logger described by `config`. Records are written by a
+/// background thread, and pending ones are flushed when the guard is dropped.
+pub fn build(config: &LogConfig) -> io::Result<(Logger, AsyncGuard)> {
+    let level: Level = config.level.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown log level {}", config.level),
+        )
+    })?;
+    let drain: Box<dyn Drain<Ok = (), Err = Never> + Send> = match &config.output {
+        LogOutput::Terminal => {
+            let decorator = TermDecorator::new().build();
+            Box::new(CompactFormat::new(decorator).build().fuse())
+        }
+        LogOutput::Json { path } => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Box::new(Json::new(file).add_default_keys().build().fuse())
+        }
+        LogOutput::Syslog { facility } => {
+            let facility = self::facility(facility).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown syslog facility {}", facility),
+                )
+            })?;
+            // A record that syslog drops should not take the server down.
+            Box::new(Syslog::connect(facility)?.ignore_res())
+        }
+    };
+    let drain = drain.filter_level(level).fuse();
+    let (drain, guard) = Async::new(drain).build_with_guard();
+    Ok((Logger::root(drain.fuse(), o!()), guard))
+}
+
+/// The syslog facility code for `name`, such as `daemon` or `local0`.
+pub fn facility(name: &str) -> Option<u8> {
+    Some(match name {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "lpr" => 6,
+        "news" => 7,
+        "uucp" => 8,
+        "cron" => 9,
+        "authpriv" => 10,
+        "ftp" => 11,
+        _ => match name.strip_prefix("local")?.parse::<u8>().ok()? {
+            n @ 0..=7 => 16 + n,
+            _ => return None,
+        },
+    })
+}
+
+/// Sends each record as an RFC 3164 message to the local syslog daemon.
+struct Syslog {
+    socket: UnixDatagram,
+    facility: u8,
+    pid: u32,
+}
+
+impl Syslog {
+    fn connect(facility: u8) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect("/dev/log")
+            .map_err(|err| io::Error::new(err.kind(), format!("/dev/log: {}", err)))?;
+        Ok(Self {
+            socket,
+            facility,
+            pid: std::process::id(),
+        })
+    }
+}
+
+impl Drain for Syslog {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        let severity = match record.level() {
+            Level::Critical => 2,
+            Level::Error => 3,
+            Level::Warning => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+        let mut message = format!(
+            "<{}>kiraftp[{}]: {}",
+            self.facility * 8 + severity,
+            self.pid,
+            record.msg()
+        );
+        let mut serializer = KeyValues(&mut message);
+        record
+            .kv()
+            .serialize(record, &mut serializer)
+            .and_then(|_| values.serialize(record, &mut serializer))
+            .map_err(io::Error::other)?;
+        self.socket.send(message.as_bytes()).map(|_| ())
+    }
+}
+
+/// Appends `, key: value` for each pair, like the terminal format does.
+struct KeyValues<'a>(&'a mut String);
+
+impl slog::Serializer for KeyValues<'_> {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        let _ = write!(self.0, ", {}: {}", key, val);
+        Ok(())
+    }
+}
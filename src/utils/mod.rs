@@ -2,5 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-only
 
 pub mod config;
+pub mod filter;
 pub mod fs;
+pub mod log;
 pub mod net;
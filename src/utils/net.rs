@@ -1,17 +1,17 @@
 // Copyright 2021 Slowy <slowyfine@gmail.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
 pub fn parse_ipv4_addr(addr: impl AsRef<str>) -> Option<SocketAddr> {
     let addr: Vec<&str> = addr.as_ref().split(',').collect();
     let addr: Result<Vec<u8>, _> = addr.iter().map(|x| x.parse()).collect();
     match addr {
-        Ok(addr) => Some(SocketAddr::from((
+        Ok(addr) if addr.len() == 6 => Some(SocketAddr::from((
             [addr[0], addr[1], addr[2], addr[3]],
             (addr[4] as u16) << 8 | (addr[5] as u16),
         ))),
-        Err(_) => None,
+        _ => None,
     }
 }
 
@@ -24,3 +24,21 @@ pub fn print_ipv4_addr(addr: SocketAddr) -> String {
         port & 0xff
     )
 }
+
+/// Parse the argument of EPRT, such as `|1|132.235.1.2|6275|` or
+/// `|2|::1|6275|`, as described in RFC 2428.
+pub fn parse_extended_addr(addr: impl AsRef<str>) -> Option<SocketAddr> {
+    let addr = addr.as_ref();
+    let delimiter = addr.chars().next().filter(|x| ('!'..='~').contains(x))?;
+    let fields: Vec<&str> = addr.split(delimiter).collect();
+    let (protocol, ip, port) = match fields[..] {
+        ["", protocol, ip, port, ""] => (protocol, ip, port),
+        _ => return None,
+    };
+    let ip: IpAddr = match protocol {
+        "1" => IpAddr::V4(ip.parse().ok()?),
+        "2" => IpAddr::V6(ip.parse().ok()?),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port.parse().ok()?))
+}
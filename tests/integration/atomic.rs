@@ -0,0 +1,165 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{sample_data, TestServer};
+use kiraftp::utils::config::{QuotaConfig, Storage, UserConfig};
+use std::{path::Path, time::Duration};
+use tokio::io::AsyncWriteExt;
+
+/// Names of the hidden files in `dir`.
+fn hidden(dir: &Path) -> Vec<String> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|x| x.starts_with('.'))
+        .collect()
+}
+
+#[tokio::test]
+async fn uploads_appear_once_complete() {
+    let server = TestServer::with_config(|config| config.atomic_uploads = true).await;
+    std::fs::write(server.root.join("a.bin"), b"old").unwrap();
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"STOR a.bin\r\n").await;
+    client.expect("150").await;
+    data_stream.write_all(&sample_data(1000)).await.unwrap();
+    let mut waited = 0;
+    while hidden(&server.root).is_empty() && waited < 100 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        waited += 1;
+    }
+    let temp = hidden(&server.root);
+    assert_eq!(temp.len(), 1);
+    assert!(temp[0].starts_with(".a.bin.") && temp[0].ends_with(".part"));
+    assert_eq!(std::fs::read(server.root.join("a.bin")).unwrap(), b"old");
+    data_stream.write_all(&sample_data(1000)).await.unwrap();
+    drop(data_stream);
+    client.expect("226").await;
+    assert_eq!(
+        std::fs::read(server.root.join("a.bin")).unwrap(),
+        [sample_data(1000), sample_data(1000)].concat()
+    );
+    assert!(hidden(&server.root).is_empty());
+}
+
+#[tokio::test]
+async fn failed_uploads_are_removed() {
+    let server = TestServer::with_config(|config| {
+        config.atomic_uploads = true;
+        config.storage = Storage::Memory { quota: Some(1000) };
+    })
+    .await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("a.bin", b"old").await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"STOR a.bin\r\n").await;
+    client.expect("150").await;
+    let _ = data_stream.write_all(&sample_data(2000)).await;
+    drop(data_stream);
+    client.expect("426").await;
+    assert_eq!(client.retr("a.bin").await, b"old");
+    let listing = client.list("").await;
+    assert_eq!(listing.lines().count(), 1, "{}", listing);
+}
+
+#[tokio::test]
+async fn uploads_without_data_connection_leave_nothing() {
+    let server = TestServer::with_config(|config| {
+        config.atomic_uploads = true;
+        let user = UserConfig {
+            home: Some("/home".into()),
+            quota: Some(QuotaConfig {
+                bytes: Some(1000),
+                files: Some(10),
+            }),
+            ..UserConfig::default()
+        };
+        config.users.insert(String::from("root"), user);
+    })
+    .await;
+    let mut client = server.login().await;
+    client.stor("a.txt", b"old").await;
+    client.expect_command("STOR new.txt", "425").await;
+    client.expect_command("STOR a.txt", "425").await;
+    let home = server.root.join("home");
+    assert!(hidden(&home).is_empty());
+    assert!(!home.join("new.txt").exists());
+    assert_eq!(std::fs::read(home.join("a.txt")).unwrap(), b"old");
+    // Nothing was counted for the uploads that never started.
+    let reply = client.expect_command("SITE QUOTA", "200").await;
+    assert!(reply.contains("Bytes: 3 of 1000 used."), "{}", reply);
+    assert!(reply.contains("Files: 1 of 10 used."), "{}", reply);
+}
+
+#[tokio::test]
+async fn partial_uploads_can_be_kept() {
+    let server = TestServer::with_config(|config| {
+        config.atomic_uploads = true;
+        config.keep_partial_uploads = true;
+        config.storage = Storage::Memory { quota: Some(1000) };
+    })
+    .await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"STOR a.bin\r\n").await;
+    client.expect("150").await;
+    let content = sample_data(2000);
+    data_stream.write_all(&content[..500]).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _ = data_stream.write_all(&content[500..]).await;
+    drop(data_stream);
+    client.expect("426").await;
+    assert_eq!(client.retr("a.bin").await, &content[..500]);
+    client.expect_command("REST 500", "350").await;
+    client.stor("a.bin", &content[500..1000]).await;
+    assert_eq!(client.retr("a.bin").await, &content[..1000]);
+}
+
+#[tokio::test]
+async fn partial_replacements_are_not_kept() {
+    let server = TestServer::with_config(|config| {
+        config.atomic_uploads = true;
+        config.keep_partial_uploads = true;
+        config.storage = Storage::Memory { quota: Some(1000) };
+    })
+    .await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("a.bin", b"old").await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"STOR a.bin\r\n").await;
+    client.expect("150").await;
+    let content = sample_data(2000);
+    data_stream.write_all(&content[..500]).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _ = data_stream.write_all(&content[500..]).await;
+    drop(data_stream);
+    client.expect("426").await;
+    assert_eq!(client.retr("a.bin").await, b"old");
+    let listing = client.list("").await;
+    assert_eq!(listing.lines().count(), 1, "{}", listing);
+}
+
+#[tokio::test]
+async fn restart_resumes_transfers() {
+    let server = TestServer::with_config(|config| config.atomic_uploads = true).await;
+    let content = sample_data(3000);
+    std::fs::write(server.root.join("a.bin"), &content[..1000]).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.expect_command("REST 1000", "350").await;
+    client.stor("a.bin", &content[1000..]).await;
+    assert_eq!(std::fs::read(server.root.join("a.bin")).unwrap(), content);
+    client.expect_command("REST 2500", "350").await;
+    assert_eq!(client.retr("a.bin").await, &content[2500..]);
+    // The offset only applies once.
+    assert_eq!(client.retr("a.bin").await, content);
+    client.expect_command("REST x", "501").await;
+    client.expect_command("REST 5000", "350").await;
+    client.expect_command("PASV", "227").await;
+    client.expect_command("STOR a.bin", "554").await;
+}
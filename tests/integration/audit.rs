@@ -0,0 +1,177 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{config, temp_dir, Client, TestServer};
+use kiraftp::{
+    audit,
+    storage::MemoryFileSystem,
+    utils::config::{AuditConfig, Config},
+    Server,
+};
+use serde_json::Value;
+use std::{path::Path, sync::Arc};
+use tokio::net::{TcpListener, TcpStream};
+
+fn entries(path: &Path) -> Vec<Value> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|x| serde_json::from_str(x).unwrap())
+        .collect()
+}
+
+async fn audited_server(hash_chain: bool) -> (TestServer, std::path::PathBuf) {
+    let mut audit_log = None;
+    let server = TestServer::with_config(|config| {
+        let path = config.path.with_file_name("audit.log");
+        config.audit = Some(AuditConfig {
+            path: path.clone(),
+            hash_chain,
+        });
+        audit_log = Some(path);
+    })
+    .await;
+    (server, audit_log.unwrap())
+}
+
+#[tokio::test]
+async fn actions_are_recorded() {
+    let (server, audit_log) = audited_server(false).await;
+    let mut client = server.client().await;
+    client.expect_command("USER nobody", "331").await;
+    client.expect_command("PASS password", "530").await;
+    client.expect_command("USER root", "331").await;
+    client.expect_command("PASS wrong", "530").await;
+    client.login("root", "password").await;
+    client.stor("a.txt", b"data").await;
+    client.expect_command("MKD dir", "257").await;
+    client.expect_command("RNFR a.txt", "350").await;
+    client.expect_command("RNTO dir/b.txt", "250").await;
+    client.expect_command("DELE dir/b.txt", "250").await;
+    client.expect_command("RMD dir", "250").await;
+    client.expect_command("RMD dir", "550").await;
+    let entries = entries(&audit_log);
+    let summary: Vec<(&str, &str, Option<&str>)> = entries
+        .iter()
+        .map(|x| {
+            (
+                x["action"].as_str().unwrap(),
+                x["result"].as_str().unwrap(),
+                x["path"].as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("login", "failure", None),
+            ("login", "failure", None),
+            ("login", "success", None),
+            ("upload", "success", Some("/a.txt")),
+            ("mkdir", "success", Some("/dir")),
+            ("rename", "success", Some("/a.txt")),
+            ("delete", "success", Some("/dir/b.txt")),
+            ("rmdir", "success", Some("/dir")),
+            ("rmdir", "failure", Some("/dir")),
+        ]
+    );
+    assert_eq!(entries[0]["user"], "nobody");
+    assert_eq!(entries[0]["reason"], "unknown user");
+    assert_eq!(entries[1]["reason"], "wrong password");
+    assert_eq!(entries[5]["to"], "/dir/b.txt");
+    assert_eq!(entries[3]["user"], "root");
+    assert_eq!(entries[3]["peer"], "127.0.0.1");
+    assert!(entries[3]["time"].as_str().unwrap().ends_with('Z'));
+    assert_eq!(entries[3]["session_id"], entries[0]["session_id"]);
+    assert!(entries.iter().all(|x| x.get("hash").is_none()));
+}
+
+#[tokio::test]
+async fn denials_are_recorded() {
+    let storage = Arc::new(MemoryFileSystem::new(None));
+    let audit_log = temp_dir().join("audit.log");
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Config {
+        audit: Some(AuditConfig {
+            path: audit_log.clone(),
+            hash_chain: false,
+        }),
+        ..config(temp_dir())
+    };
+    let server = Server::builder()
+        .config(config)
+        .listener(listener)
+        .storage(storage.clone())
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(server.run());
+    let mut client = Client::new(TcpStream::connect(addr).await.unwrap()).await;
+    client.login("root", "password").await;
+    client.expect_command("MKD locked", "257").await;
+    client.stor("locked/a.txt", b"data").await;
+    storage.set_permissions("/locked", 0o555).unwrap();
+    let _data_stream = client.pasv().await;
+    client.expect_command("STOR locked/b.txt", "553").await;
+    client.expect_command("DELE locked/a.txt", "550").await;
+    storage.set_permissions("/locked/a.txt", 0o200).unwrap();
+    client.expect_command("PASV", "227").await;
+    client.expect_command("RETR locked/a.txt", "550").await;
+    let denied: Vec<(String, String)> = entries(&audit_log)
+        .iter()
+        .filter(|x| x["result"] == "denied")
+        .map(|x| (x["action"].to_string(), x["path"].to_string()))
+        .collect();
+    assert_eq!(
+        denied,
+        [
+            ("\"upload\"".into(), "\"/locked/b.txt\"".into()),
+            ("\"delete\"".into(), "\"/locked/a.txt\"".into()),
+            ("\"download\"".into(), "\"/locked/a.txt\"".into()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn hash_chain_detects_tampering() {
+    let (mut server, audit_log) = audited_server(true).await;
+    let mut client = server.login().await;
+    client.expect_command("MKD one", "257").await;
+    client.expect_command("QUIT", "221").await;
+    server.stop().await;
+    assert_eq!(audit::verify(&audit_log), Ok(2));
+    // A restarted server continues the chain.
+    let config = server.config.clone();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let restarted = Server::builder()
+        .config(config)
+        .listener(listener)
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(restarted.run());
+    let mut client = Client::new(TcpStream::connect(addr).await.unwrap()).await;
+    client.login("root", "password").await;
+    client.expect_command("MKD two", "257").await;
+    assert_eq!(audit::verify(&audit_log), Ok(4));
+    let entries = entries(&audit_log);
+    assert!(entries[0].get("prev").is_none());
+    assert_eq!(entries[2]["prev"], entries[1]["hash"]);
+
+    let log = std::fs::read_to_string(&audit_log).unwrap();
+    let altered = log.replacen("/one", "/won", 1);
+    std::fs::write(&audit_log, altered).unwrap();
+    assert_eq!(
+        audit::verify(&audit_log),
+        Err(String::from("line 2: hash does not match"))
+    );
+    let mut lines: Vec<&str> = log.lines().collect();
+    lines.remove(1);
+    std::fs::write(&audit_log, lines.join("\n")).unwrap();
+    assert_eq!(
+        audit::verify(&audit_log),
+        Err(String::from("line 2: does not follow the entry before it"))
+    );
+}
@@ -0,0 +1,166 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::TestServer;
+use kiraftp::utils::{
+    config::UserConfig,
+    net::{parse_extended_addr, parse_ipv4_addr, print_ipv4_addr},
+};
+use std::net::SocketAddr;
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpSocket, TcpStream},
+};
+
+async fn fxp_server() -> TestServer {
+    TestServer::with_config(|config| {
+        let user = UserConfig {
+            allow_fxp: true,
+            ..UserConfig::default()
+        };
+        config.users.insert(String::from("root"), user);
+    })
+    .await
+}
+
+/// Connect to the address of a 227 reply from another loopback address.
+async fn connect_from_elsewhere(reply: &str) -> TcpStream {
+    let addr = &reply[reply.find('(').unwrap() + 1..reply.find(')').unwrap()];
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.2:0".parse().unwrap()).unwrap();
+    socket
+        .connect(parse_ipv4_addr(addr).unwrap())
+        .await
+        .unwrap()
+}
+
+fn port_argument(addr: SocketAddr) -> String {
+    let addr = print_ipv4_addr(addr);
+    addr[1..addr.len() - 1].to_string()
+}
+
+#[test]
+fn extended_addresses() {
+    assert_eq!(
+        parse_extended_addr("|1|132.235.1.2|6275|"),
+        Some("132.235.1.2:6275".parse().unwrap())
+    );
+    assert_eq!(
+        parse_extended_addr("!2!1080::8:800:200C:417A!5282!"),
+        Some("[1080::8:800:200C:417A]:5282".parse().unwrap())
+    );
+    assert_eq!(parse_extended_addr("|3|132.235.1.2|6275|"), None);
+    assert_eq!(parse_extended_addr("|1|::1|6275|"), None);
+    assert_eq!(parse_extended_addr("|1|132.235.1.2|6275"), None);
+}
+
+#[tokio::test]
+async fn port_must_name_the_client() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    client.expect_command("PORT 127,0,0,2,4,1", "504").await;
+    client.expect_command("PORT 127,0,0,1,0,80", "504").await;
+    client
+        .expect_command("EPRT |1|127.0.0.2|1025|", "504")
+        .await;
+    client.expect_command("EPRT |1|127.0.0.1|21|", "504").await;
+    client
+        .expect_command("EPRT |3|127.0.0.1|1025|", "522")
+        .await;
+    client.expect_command("EPRT |1|127.0.0.1|", "501").await;
+    // Nothing was left prepared by the refused commands.
+    client.expect_command("LIST", "425").await;
+}
+
+#[tokio::test]
+async fn eprt_transfers() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"data").unwrap();
+    let mut client = server.login().await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    client
+        .expect_command(&format!("EPRT |1|127.0.0.1|{}|", port), "200")
+        .await;
+    client.send(b"RETR a.txt\r\n").await;
+    let (mut data_stream, _) = listener.accept().await.unwrap();
+    let mut received = Vec::new();
+    data_stream.read_to_end(&mut received).await.unwrap();
+    client.expect("150").await;
+    client.expect("226").await;
+    assert_eq!(received, b"data");
+}
+
+#[tokio::test]
+async fn fxp_is_opt_in() {
+    let server = fxp_server().await;
+    std::fs::write(server.root.join("a.txt"), b"data").unwrap();
+    let mut client = server.login().await;
+    let elsewhere = TcpListener::bind("127.0.0.2:0").await.unwrap();
+    let argument = port_argument(elsewhere.local_addr().unwrap());
+    client
+        .expect_command(&format!("PORT {}", argument), "200")
+        .await;
+    client.send(b"RETR a.txt\r\n").await;
+    let (mut data_stream, _) = elsewhere.accept().await.unwrap();
+    let mut received = Vec::new();
+    data_stream.read_to_end(&mut received).await.unwrap();
+    client.expect("150").await;
+    client.expect("226").await;
+    assert_eq!(received, b"data");
+    // Privileged ports stay out of reach.
+    client.expect_command("PORT 127,0,0,2,0,25", "504").await;
+}
+
+#[tokio::test]
+async fn passive_waits_for_the_client() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"data").unwrap();
+    let mut client = server.login().await;
+    let reply = client.expect_command("PASV", "227").await;
+    let mut intruder = connect_from_elsewhere(&reply).await;
+    client.send(b"RETR a.txt\r\n").await;
+    // The intruder is turned away without seeing any data.
+    let mut stolen = Vec::new();
+    intruder.read_to_end(&mut stolen).await.unwrap();
+    assert!(stolen.is_empty());
+    let addr = &reply[reply.find('(').unwrap() + 1..reply.find(')').unwrap()];
+    let mut data_stream = TcpStream::connect(parse_ipv4_addr(addr).unwrap())
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    data_stream.read_to_end(&mut received).await.unwrap();
+    client.expect("150").await;
+    client.expect("226").await;
+    assert_eq!(received, b"data");
+}
+
+#[tokio::test]
+async fn passive_peer_check_can_be_disabled() {
+    let server = TestServer::with_config(|config| config.check_passive_peer = false).await;
+    std::fs::write(server.root.join("a.txt"), b"data").unwrap();
+    let mut client = server.login().await;
+    let reply = client.expect_command("PASV", "227").await;
+    let mut data_stream = connect_from_elsewhere(&reply).await;
+    client.send(b"RETR a.txt\r\n").await;
+    let mut received = Vec::new();
+    data_stream.read_to_end(&mut received).await.unwrap();
+    client.expect("150").await;
+    client.expect("226").await;
+    assert_eq!(received, b"data");
+}
+
+#[tokio::test]
+async fn fxp_users_take_passive_connections_from_anywhere() {
+    let server = fxp_server().await;
+    std::fs::write(server.root.join("a.txt"), b"data").unwrap();
+    let mut client = server.login().await;
+    let reply = client.expect_command("PASV", "227").await;
+    let mut data_stream = connect_from_elsewhere(&reply).await;
+    client.send(b"RETR a.txt\r\n").await;
+    let mut received = Vec::new();
+    data_stream.read_to_end(&mut received).await.unwrap();
+    client.expect("150").await;
+    client.expect("226").await;
+    assert_eq!(received, b"data");
+}
@@ -0,0 +1,102 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::temp_dir;
+use std::process::{Command, Output};
+
+fn kiraftp(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kiraftp"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn default_config_needs_filling_in() {
+    let output = kiraftp(&["print-default-config"]);
+    assert!(output.status.success());
+    let dir = temp_dir();
+    let path = dir.join("kiraftp.yaml");
+    std::fs::write(&path, &output.stdout).unwrap();
+    let output = kiraftp(&["check-config", "--config", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for setting in ["username:", "password:", "path:"] {
+        assert!(stderr.contains(setting), "{}", stderr);
+    }
+    let filled = String::from_utf8_lossy(&std::fs::read(&path).unwrap())
+        .replace("username: \"\"", "username: alice")
+        .replace("password: \"\"", "password: secret")
+        .replace("path: \"\"", &format!("path: {}", dir.display()));
+    std::fs::write(&path, filled).unwrap();
+    let output = kiraftp(&["check-config", "--config", path.to_str().unwrap()]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Config OK\n");
+}
+
+#[test]
+fn overrides_are_validated() {
+    let dir = temp_dir();
+    let path = dir.join("kiraftp.yaml");
+    let root = dir.join("root");
+    std::fs::write(
+        &path,
+        format!("username: a\npassword: b\npath: {}\n", root.display()),
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+    let output = kiraftp(&["-c", path, "check-config"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("path:"));
+    let output = kiraftp(&["-c", path, "--root", dir.to_str().unwrap(), "check-config"]);
+    assert!(output.status.success(), "{:?}", output);
+    let output = kiraftp(&[
+        "check-config",
+        "-c",
+        path,
+        "--root",
+        dir.to_str().unwrap(),
+        "--port",
+        "0",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("port:"));
+}
+
+#[test]
+fn bad_arguments_are_rejected() {
+    assert_eq!(kiraftp(&["--port", "70000"]).status.code(), Some(2));
+    assert_eq!(kiraftp(&["--log-level", "loud"]).status.code(), Some(2));
+    assert_eq!(kiraftp(&["frobnicate"]).status.code(), Some(2));
+}
+
+#[test]
+fn serve_fails_without_config() {
+    let dir = temp_dir();
+    let output = kiraftp(&[
+        "--config",
+        dir.join("missing.yaml").to_str().unwrap(),
+        "--foreground",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid"));
+}
+
+#[test]
+fn effective_config_comes_from_environment() {
+    let dir = temp_dir();
+    let output = Command::new(env!("CARGO_BIN_EXE_kiraftp"))
+        .arg("print-config")
+        .current_dir(&dir)
+        .env("KIRAFTP_USERNAME", "a")
+        .env("KIRAFTP_PASSWORD", "hunter2")
+        .env("KIRAFTP_PATH", &dir)
+        .env("KIRAFTP_PORT", "2121")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("port: 2121"), "{}", stdout);
+    assert!(stdout.contains("password: (redacted)"), "{}", stdout);
+    assert!(!stdout.contains("hunter2"));
+}
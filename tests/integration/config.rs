@@ -0,0 +1,329 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{config, temp_dir};
+use kiraftp::utils::config::{Config, Storage};
+
+fn parse(yaml: &str) -> Result<Config, serde_yaml::Error> {
+    serde_yaml::from_str(yaml)
+}
+
+#[test]
+fn missing_fields_take_defaults() {
+    let config = parse("username: a\npassword: b\npath: /srv\n").unwrap();
+    assert_eq!(config.address(), "0.0.0.0:21".parse().unwrap());
+    assert_eq!(config.storage, Storage::Local);
+    assert_eq!(config.shutdown_timeout, 30);
+    assert!(!config.watch_config);
+}
+
+#[test]
+fn credentials_and_path_are_required() {
+    for yaml in [
+        "password: b\npath: /srv\n",
+        "username: a\npath: /srv\n",
+        "username: a\npassword: b\n",
+    ] {
+        assert!(parse(yaml).is_err(), "{:?} was accepted", yaml);
+    }
+}
+
+#[test]
+fn unknown_fields_are_rejected() {
+    let err = parse("username: a\npassword: b\npath: /srv\nprot: 2121\n").unwrap_err();
+    assert!(err.to_string().contains("prot"), "{}", err);
+    let err = parse("username: a\npassword: b\npath: /srv\nstorage:\n  type: memory\n  qouta: 1\n")
+        .unwrap_err();
+    assert!(err.to_string().contains("qouta"), "{}", err);
+    let err = parse(
+        "username: a\npassword: b\npath: /srv\nstorage:\n  type: s3\n  endpoint: http://s3\n  \
+         bucket: b\n  access_key: a\n  secret_key: s\n  regoin: x\n",
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("regoin"), "{}", err);
+}
+
+#[test]
+fn validation_reports_every_problem() {
+    let config = Config {
+        port: 0,
+        username: String::new(),
+        ..config(temp_dir().join("missing"))
+    };
+    let problems = config.validate().unwrap_err();
+    assert_eq!(problems.len(), 3, "{:?}", problems);
+    assert!(problems[0].starts_with("port:"));
+    assert!(problems[1].starts_with("username:"));
+    assert!(problems[2].starts_with("path:"));
+}
+
+#[test]
+fn defaults_must_be_filled_in() {
+    let problems = Config::default().validate().unwrap_err();
+    assert_eq!(problems.len(), 3, "{:?}", problems);
+    assert!(problems[0].starts_with("username:"));
+    assert!(problems[1].starts_with("password:"));
+    assert!(problems[2].starts_with("path:"));
+}
+
+#[test]
+fn validation_accepts_a_usable_config() {
+    config(temp_dir()).validate().unwrap();
+    // The path is not used by memory storage.
+    let config = Config {
+        storage: Storage::Memory { quota: None },
+        ..config(temp_dir().join("missing"))
+    };
+    config.validate().unwrap();
+}
+
+#[test]
+fn validation_checks_s3_settings() {
+    let config = parse(
+        "username: a\npassword: b\npath: /srv\nstorage:\n  type: s3\n  endpoint: s3.local\n  \
+         bucket: ''\n  access_key: a\n  secret_key: s\n  part_size: 1024\n",
+    )
+    .unwrap();
+    let problems = config.validate().unwrap_err();
+    assert!(problems.iter().any(|x| x.starts_with("storage.endpoint:")));
+    assert!(problems.iter().any(|x| x.starts_with("storage.bucket:")));
+    assert!(problems.iter().any(|x| x.starts_with("storage.part_size:")));
+}
+
+#[test]
+fn validation_checks_passive_settings() {
+    let config = parse(
+        "username: a\npassword: b\npath: /\npassive_ports:\n  min: 5000\n  max: 4000\n\
+         max_passive_ports: 0\n",
+    )
+    .unwrap();
+    let problems = config.validate().unwrap_err();
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert!(problems[0].starts_with("passive_ports:"));
+    assert!(problems[1].starts_with("max_passive_ports:"));
+}
+
+fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn environment_overrides_file() {
+    let dir = temp_dir();
+    let path = dir.join("kiraftp.yaml");
+    std::fs::write(&path, "username: a\npassword: b\npath: /srv\nport: 2121\n").unwrap();
+    let config = Config::load(
+        Some(&path),
+        vars(&[
+            ("KIRAFTP_PORT", "2222"),
+            ("KIRAFTP_USERNAME", "carol"),
+            ("KIRAFTP_CONFIG", "ignored.yaml"),
+            ("HOME", "/root"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(config.port, 2222);
+    assert_eq!(config.username, "carol");
+    assert_eq!(config.password, "b");
+}
+
+#[test]
+fn environment_alone_is_enough() {
+    let config = Config::load(
+        None,
+        vars(&[
+            ("KIRAFTP_USERNAME", "a"),
+            ("KIRAFTP_PASSWORD", "0x10"),
+            ("KIRAFTP_PATH", "/srv"),
+            ("KIRAFTP_WATCH_CONFIG", "true"),
+            ("KIRAFTP_STORAGE__TYPE", "memory"),
+            ("KIRAFTP_STORAGE__QUOTA", "1024"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(config.password, "0x10");
+    assert!(config.watch_config);
+    assert_eq!(config.storage, Storage::Memory { quota: Some(1024) });
+    // Numbers are still accepted where a string is expected.
+    let config = Config::load(
+        None,
+        vars(&[
+            ("KIRAFTP_USERNAME", "a"),
+            ("KIRAFTP_PASSWORD", "1234"),
+            ("KIRAFTP_PATH", "/srv"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(config.password, "1234");
+}
+
+#[test]
+fn unknown_variables_are_ignored() {
+    let env = vars(&[
+        ("KIRAFTP_USERNAME", "a"),
+        ("KIRAFTP_PASSWORD_FILE", "/dev/null"),
+        ("KIRAFTP_PASSWORD", "b"),
+        ("KIRAFTP_PATH", "/srv"),
+        ("KIRAFTP_PROT", "21"),
+        ("KIRAFTP_CONFIG", "ignored.yaml"),
+        ("KIRAFTP_HOOKS__MAX_RUNNING", "2"),
+    ]);
+    let config = Config::load(None, env.clone()).unwrap();
+    assert_eq!(config.port, Config::default().port);
+    assert_eq!(config.hooks.max_running, 2);
+    assert_eq!(
+        Config::unknown_vars(env),
+        ["KIRAFTP_PROT", "KIRAFTP_CONFIG"]
+    );
+}
+
+#[test]
+fn users_keep_the_case_of_the_file() {
+    let dir = temp_dir();
+    let path = dir.join("kiraftp.yaml");
+    std::fs::write(
+        &path,
+        "username: a\npassword: b\npath: /srv\nusers:\n  Alice:\n    allow_fxp: false\n",
+    )
+    .unwrap();
+    let config = Config::load(
+        Some(&path),
+        vars(&[
+            ("KIRAFTP_USERS__ALICE__ALLOW_FXP", "true"),
+            ("KIRAFTP_USERS__BOB__ALLOW_FXP", "true"),
+        ]),
+    )
+    .unwrap();
+    assert!(config.user("Alice").allow_fxp);
+    assert!(config.user("bob").allow_fxp);
+    assert_eq!(config.users.len(), 2);
+}
+
+#[test]
+fn toml_files_are_read() {
+    let dir = temp_dir();
+    let path = dir.join("kiraftp.toml");
+    std::fs::write(
+        &path,
+        "username = \"a\"\npassword = \"b\"\npath = \"/srv\"\n\n[storage]\ntype = \"memory\"\n",
+    )
+    .unwrap();
+    let config = Config::load(Some(&path), Vec::new()).unwrap();
+    assert_eq!(config.username, "a");
+    assert_eq!(config.storage, Storage::Memory { quota: None });
+}
+
+#[test]
+fn secrets_are_read_from_files() {
+    let dir = temp_dir();
+    let secret = dir.join("secret");
+    std::fs::write(&secret, "hunter2\n").unwrap();
+    let path = dir.join("kiraftp.yaml");
+    std::fs::write(
+        &path,
+        format!(
+            "username: a\npassword_file: {}\npath: /srv\n",
+            secret.display()
+        ),
+    )
+    .unwrap();
+    let config = Config::load(Some(&path), Vec::new()).unwrap();
+    assert_eq!(config.password, "hunter2");
+    // A password from the environment replaces the file from the layer below.
+    let config = Config::load(Some(&path), vars(&[("KIRAFTP_PASSWORD", "env")])).unwrap();
+    assert_eq!(config.password, "env");
+    std::fs::write(
+        &path,
+        format!(
+            "username: a\npassword: b\npassword_file: {}\npath: /srv\n",
+            secret.display()
+        ),
+    )
+    .unwrap();
+    let err = Config::load(Some(&path), Vec::new()).unwrap_err();
+    assert!(err.contains("both set"), "{}", err);
+    let err = Config::load(
+        None,
+        vars(&[(
+            "KIRAFTP_PASSWORD_FILE",
+            dir.join("missing").to_str().unwrap(),
+        )]),
+    )
+    .unwrap_err();
+    assert!(err.starts_with("password_file:"), "{}", err);
+}
+
+#[test]
+fn redacted_hides_secrets() {
+    let config = Config {
+        password: String::from("hunter2"),
+        ..config("/srv".into())
+    };
+    let redacted = config.redacted();
+    assert!(redacted.contains("password: (redacted)"), "{}", redacted);
+    assert!(!redacted.contains("hunter2"));
+    assert!(redacted.contains("username: root"));
+}
+
+#[test]
+fn user_settings() {
+    let dir = temp_dir();
+    let path = dir.join("kiraftp.yaml");
+    std::fs::write(
+        &path,
+        "username: a\npassword: b\npath: /srv\nusers:\n  anonymous:\n    allow_fxp: false\n",
+    )
+    .unwrap();
+    let config = Config::load(
+        Some(&path),
+        vars(&[("KIRAFTP_USERS__A__ALLOW_FXP", "true")]),
+    )
+    .unwrap();
+    assert!(config.user("a").allow_fxp);
+    assert!(!config.user("anonymous").allow_fxp);
+    assert_eq!(config.user("nobody"), Default::default());
+}
+
+#[test]
+fn validation_checks_hooks() {
+    let config = parse(
+        "username: a\npassword: b\npath: /\nhooks:\n  max_running: 0\n  run:\n    \
+         - type: webhook\n      url: https://example.com/\n    - type: command\n      \
+         command: /bin/true\n      events: [upload, delete]\n",
+    )
+    .unwrap();
+    let problems = config.validate().unwrap_err();
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert!(problems[0].starts_with("hooks.max_running:"));
+    assert!(problems[1].starts_with("hooks.run:"));
+}
+
+#[test]
+fn validation_checks_upload_filters() {
+    let config = parse(
+        "username: a\npassword: b\npath: /\nupload_filter:\n  deny:\n    - glob: '*.exe'\n    \
+         - regex: '(unclosed'\nusers:\n  bob:\n    upload_filter:\n      allow:\n        \
+         - extension: txt\n  eve:\n    upload_filter:\n      deny:\n        - glob: 'a[z'\n",
+    )
+    .unwrap();
+    let problems = config.validate().unwrap_err();
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert!(problems[0].starts_with("upload_filter:"));
+    assert!(problems[1].starts_with("users.eve.upload_filter:"));
+}
+
+#[test]
+fn validation_checks_quota_homes() {
+    let config = parse(
+        "username: a\npassword: b\npath: /\nusers:\n  a:\n    quota:\n      bytes: 10\n  \
+         bob:\n    home: /data\n    quota:\n      files: 1\n  eve:\n    home: /data/eve\n  \
+         sam:\n    home: /sam\n    quota:\n      bytes: 10\n",
+    )
+    .unwrap();
+    let problems = config.validate().unwrap_err();
+    assert_eq!(problems.len(), 2, "{:?}", problems);
+    assert!(problems[0].starts_with("users.a.quota:"));
+    assert!(problems[1].starts_with("users.bob.home: holds the home of eve"));
+}
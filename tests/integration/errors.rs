@@ -0,0 +1,74 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::TestServer;
+
+#[tokio::test]
+async fn unknown_command() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    client.expect_command("XYZZY", "500").await;
+    client.expect_command("XYZZY plugh", "500").await;
+}
+
+#[tokio::test]
+async fn transfer_without_data_connection() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"").unwrap();
+    let mut client = server.login().await;
+    client.expect_command("RETR a.txt", "425").await;
+    client.expect_command("STOR b.txt", "425").await;
+    client.expect_command("LIST", "425").await;
+}
+
+#[tokio::test]
+async fn retr_missing_file() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    client.expect_command("PASV", "227").await;
+    client.expect_command("RETR missing.txt", "550").await;
+    // The failed command used up the data connection setup.
+    client.expect_command("RETR missing.txt", "550").await;
+    client.expect_command("LIST", "425").await;
+}
+
+#[tokio::test]
+async fn retr_directory() {
+    let server = TestServer::start().await;
+    std::fs::create_dir(server.root.join("dir")).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("PASV", "227").await;
+    client.expect_command("RETR dir", "550").await;
+}
+
+#[tokio::test]
+async fn stor_into_missing_directory() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    let _data_stream = client.pasv().await;
+    client.expect_command("STOR missing/a.txt", "553").await;
+    assert!(!server.root.join("missing").exists());
+}
+
+#[tokio::test]
+async fn cwd_to_missing_directory() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"").unwrap();
+    let mut client = server.login().await;
+    client.expect_command("CWD missing", "550").await;
+    client.expect_command("CWD a.txt", "550").await;
+    client.expect_command("PWD", "257 \"/\"").await;
+}
+
+#[tokio::test]
+async fn bad_parameters() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    client.expect_command("PORT 1,2,3", "501").await;
+    client.expect_command("PORT a,b,c,d,e,f", "501").await;
+    client.expect_command("TYPE X", "504").await;
+    client.expect_command("MODE B", "504").await;
+    client.expect_command("MODE S", "200").await;
+    client.expect_command("STRU R", "504").await;
+    client.expect_command("STRU F", "200").await;
+}
@@ -0,0 +1,66 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::TestServer;
+use kiraftp::utils::config::{FilterConfig, NamePattern, UserConfig};
+
+#[tokio::test]
+async fn refused_names_get_553() {
+    let server = TestServer::with_config(|config| {
+        config.upload_filter = FilterConfig {
+            deny: vec![
+                NamePattern::Extension(String::from("exe")),
+                NamePattern::Glob(String::from("*.sh")),
+            ],
+            max_length: Some(12),
+            deny_dotfiles: true,
+            deny_control_characters: true,
+            ..FilterConfig::default()
+        };
+    })
+    .await;
+    let mut client = server.login().await;
+    client.expect_command("PASV", "227").await;
+    client
+        .expect_command("STOR setup.EXE", "553 File name not allowed")
+        .await;
+    // Nothing was left waiting for a data connection.
+    client.expect_command("LIST", "425").await;
+    client.expect_command("APPE run.sh", "553").await;
+    client.expect_command("STOR .htaccess", "553").await;
+    client.expect_command("STOR a\tb.txt", "553").await;
+    client.expect_command("STOR much-too-long.txt", "553").await;
+    client.expect_command("MKD .git", "553").await;
+    client.expect_command("MKD dir", "257").await;
+    client.stor("dir/notes.txt", b"notes").await;
+    client.expect_command("RNFR dir/notes.txt", "350").await;
+    client.expect_command("RNTO dir/notes.exe", "553").await;
+    client.expect_command("RNFR dir/notes.txt", "350").await;
+    client.expect_command("RNTO notes.md", "250").await;
+    assert!(!server.root.join("setup.EXE").exists());
+    assert!(!server.root.join(".git").exists());
+    assert!(!server.root.join("dir/notes.exe").exists());
+    assert!(server.root.join("notes.md").exists());
+}
+
+#[tokio::test]
+async fn users_can_have_their_own_rules() {
+    let server = TestServer::with_config(|config| {
+        config.upload_filter.deny_dotfiles = true;
+        let user = UserConfig {
+            upload_filter: Some(FilterConfig {
+                allow: vec![NamePattern::Regex(String::from(r"\.txt$"))],
+                ..FilterConfig::default()
+            }),
+            ..UserConfig::default()
+        };
+        config.users.insert(String::from("root"), user);
+    })
+    .await;
+    let mut client = server.login().await;
+    client.stor("notes.txt", b"notes").await;
+    client.expect_command("STOR notes.md", "553").await;
+    client.expect_command("MKD dir", "553").await;
+    // The global rules do not apply to the user.
+    client.stor(".txt", b"hidden").await;
+}
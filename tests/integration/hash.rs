@@ -0,0 +1,120 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::TestServer;
+
+const SHA256: &str = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+#[tokio::test]
+async fn hash_with_each_algorithm() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"hello world").unwrap();
+    let mut client = server.login().await;
+    let reply = client.expect_command("FEAT", "221").await;
+    assert!(
+        reply.contains(" HASH SHA-1;SHA-256*;SHA-512;MD5;CRC32\n"),
+        "{}",
+        reply
+    );
+    client
+        .expect_command("HASH a.txt", &format!("213 SHA-256 0-11 {} a.txt", SHA256))
+        .await;
+    client.expect_command("OPTS HASH", "200 SHA-256").await;
+    let expected = [
+        ("SHA-1", "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed"),
+        (
+            "SHA-512",
+            "309ecc489c12d6eb4cc40f50c902f2b4d0ed77ee511a7c7a9bcd3ca86d4cd86f\
+             989dd35bc5ff499670da34255b45b0cfd830e81f605dcf7dc5542e93ae9cd76f",
+        ),
+        ("MD5", "5eb63bbbe01eeed093cb22bb8f5acdc3"),
+        ("CRC32", "0d4a1185"),
+    ];
+    for (algorithm, checksum) in expected {
+        client
+            .expect_command(&format!("OPTS HASH {}", algorithm), "200")
+            .await;
+        client
+            .expect_command(
+                "HASH /a.txt",
+                &format!("213 {} 0-11 {} /a.txt", algorithm, checksum),
+            )
+            .await;
+    }
+    client.expect_command("OPTS HASH sha-1", "200 SHA-1").await;
+    client.expect_command("OPTS HASH WHIRLPOOL", "504").await;
+    let reply = client.expect_command("FEAT", "221").await;
+    assert!(reply.contains(" HASH SHA-1*;SHA-256;"), "{}", reply);
+}
+
+#[tokio::test]
+async fn hash_a_range() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"hello world").unwrap();
+    let mut client = server.login().await;
+    client
+        .expect_command("RANG 3 7", "350 Restarting at 3. Ending at 7.")
+        .await;
+    client
+        .expect_command(
+            "HASH a.txt",
+            "213 SHA-256 3-8 c245b39611586f6401e743185b2bcd1c75e2a593aebbd2a55da056fd75e3012c",
+        )
+        .await;
+    // The range only applies to one HASH.
+    client
+        .expect_command("HASH a.txt", "213 SHA-256 0-11")
+        .await;
+    client.expect_command("RANG 6 100", "350").await;
+    client.expect_command("RANG 1 0", "350").await;
+    client
+        .expect_command("HASH a.txt", "213 SHA-256 0-11")
+        .await;
+    client.expect_command("RANG 5 2", "501").await;
+    client.expect_command("RANG 20 30", "350").await;
+    client.expect_command("HASH a.txt", "501").await;
+}
+
+#[tokio::test]
+async fn legacy_hash_commands() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"hello world").unwrap();
+    std::fs::write(server.root.join("file 2"), b"hello world").unwrap();
+    let mut client = server.login().await;
+    client
+        .expect_command("XMD5 a.txt", "250 5eb63bbbe01eeed093cb22bb8f5acdc3")
+        .await;
+    client
+        .expect_command(
+            "XSHA1 a.txt",
+            "250 2aae6c35c94fcfb415dbe95f408b9ce91ee846ed",
+        )
+        .await;
+    client
+        .expect_command("XSHA256 a.txt", &format!("250 {}", SHA256))
+        .await;
+    client.expect_command("XCRC a.txt", "250 0d4a1185").await;
+    // From an offset, and between two.
+    client
+        .expect_command("XMD5 a.txt 6", "250 7d793037a0760186574b0282f2f435e7")
+        .await;
+    client
+        .expect_command(
+            "XSHA256 a.txt 3 8",
+            "250 c245b39611586f6401e743185b2bcd1c75e2a593aebbd2a55da056fd75e3012c",
+        )
+        .await;
+    client
+        .expect_command("XCRC \"file 2\"", "250 0d4a1185")
+        .await;
+    client.expect_command("XCRC missing.txt", "550").await;
+    client.expect_command("XCRC /", "550").await;
+}
+
+#[tokio::test]
+async fn hash_needs_login() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.expect_command("HASH a.txt", "530").await;
+    client.expect_command("XMD5 a.txt", "530").await;
+}
@@ -0,0 +1,59 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::TestServer;
+use kiraftp::utils::config::UserConfig;
+
+async fn home_server(home: &str) -> TestServer {
+    TestServer::with_config(|config| {
+        let user = UserConfig {
+            home: Some(home.into()),
+            ..UserConfig::default()
+        };
+        config.users.insert(String::from("root"), user);
+    })
+    .await
+}
+
+#[tokio::test]
+async fn users_see_their_home_as_root() {
+    let server = home_server("/home/root").await;
+    std::fs::write(server.root.join("outside.txt"), b"outside").unwrap();
+    let mut client = server.login().await;
+    client.expect_command("PWD", "257 \"/\"").await;
+    // The home and its parents are created at login.
+    let home = server.root.join("home/root");
+    assert!(home.is_dir());
+    client.stor("a.txt", b"inside").await;
+    assert_eq!(std::fs::read(home.join("a.txt")).unwrap(), b"inside");
+    client.expect_command("MKD dir", "257").await;
+    assert!(home.join("dir").is_dir());
+    client.expect_command("RNFR a.txt", "350").await;
+    client.expect_command("RNTO dir/b.txt", "250").await;
+    assert!(home.join("dir/b.txt").exists());
+    assert_eq!(client.list("dir").await.lines().count(), 1);
+}
+
+#[tokio::test]
+async fn home_cannot_be_left() {
+    let server = home_server("sub").await;
+    std::fs::write(server.root.join("secret.txt"), b"secret").unwrap();
+    let mut client = server.login().await;
+    client.expect_command("CWD ..", "250").await;
+    client.expect_command("PWD", "257 \"/\"").await;
+    assert_eq!(client.list("/..").await, "");
+    client.expect_command("PASV", "227").await;
+    client.expect_command("RETR ../secret.txt", "550").await;
+    client.expect_command("DELE /../secret.txt", "550").await;
+    assert!(server.root.join("secret.txt").exists());
+}
+
+#[tokio::test]
+async fn other_users_see_everything() {
+    let server = home_server("/home/root").await;
+    std::fs::create_dir_all(server.root.join("home/root")).unwrap();
+    std::fs::write(server.root.join("home/root/a.txt"), b"a").unwrap();
+    let mut client = server.client().await;
+    client.login("anonymous", "guest").await;
+    assert_eq!(client.retr("home/root/a.txt").await, b"a");
+}
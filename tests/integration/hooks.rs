@@ -0,0 +1,227 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::TestServer;
+use kiraftp::utils::config::{Hook, HookEvent, Storage, UserConfig};
+use serde_json::Value;
+use std::{path::Path, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+fn shell(script: &str) -> Hook {
+    Hook::Command {
+        command: "/bin/sh".into(),
+        args: vec![String::from("-c"), String::from(script)],
+        events: Vec::new(),
+    }
+}
+
+/// The lines of `path` once there are `count` of them, sorted.
+async fn wait_for_lines(path: &Path, count: usize) -> Vec<String> {
+    for _ in 0..200 {
+        if let Ok(content) = std::fs::read_to_string(path) {
+            let mut lines: Vec<String> = content.lines().map(String::from).collect();
+            if lines.len() >= count {
+                lines.sort();
+                return lines;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("{:?} did not get {} lines", path, count);
+}
+
+#[tokio::test]
+async fn commands_are_told_about_changes() {
+    let mut log = None;
+    let server = TestServer::with_config(|config| {
+        let path = config.path.with_file_name("hooks.log");
+        config.hooks.run.push(shell(&format!(
+            "echo \"$KIRAFTP_EVENT $KIRAFTP_PATH $KIRAFTP_FROM $KIRAFTP_USER $KIRAFTP_SIZE\" >> {}",
+            path.display()
+        )));
+        log = Some(path);
+    })
+    .await;
+    let log = log.unwrap();
+    let mut client = server.login().await;
+    client.stor("a.txt", b"data").await;
+    wait_for_lines(&log, 1).await;
+    client.upload("APPE a.txt", b"more").await;
+    wait_for_lines(&log, 2).await;
+    client.expect_command("RNFR a.txt", "350").await;
+    client.expect_command("RNTO b.txt", "250").await;
+    wait_for_lines(&log, 3).await;
+    client.expect_command("DELE b.txt", "250").await;
+    // Failed commands run nothing.
+    client.expect_command("DELE b.txt", "550").await;
+    assert_eq!(
+        wait_for_lines(&log, 4).await,
+        [
+            "append /a.txt  root 8",
+            "delete /b.txt  root 8",
+            "rename /b.txt /a.txt root 8",
+            "upload /a.txt  root 4",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn webhooks_receive_events() {
+    let endpoint = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", endpoint.local_addr().unwrap());
+    let server = TestServer::with_config(|config| {
+        config.hooks.run.push(Hook::Webhook {
+            url,
+            events: vec![HookEvent::Delete],
+        })
+    })
+    .await;
+    std::fs::write(server.root.join("a.txt"), b"data").unwrap();
+    let mut client = server.login().await;
+    client.stor("b.txt", b"not told").await;
+    client.expect_command("DELE a.txt", "250").await;
+    let (mut stream, _) = endpoint.accept().await.unwrap();
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    let body = loop {
+        let len = stream.read(&mut buffer).await.unwrap();
+        request.extend_from_slice(&buffer[..len]);
+        let text = String::from_utf8_lossy(&request).into_owned();
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            if head.contains(&format!("Content-Length: {}", body.len())) {
+                assert!(head.starts_with("POST /hook HTTP/1.1\r\n"), "{}", head);
+                break body.to_string();
+            }
+        }
+    };
+    stream
+        .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+        .await
+        .unwrap();
+    let event: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(event["event"], "delete");
+    assert_eq!(event["path"], "/a.txt");
+    assert_eq!(event["user"], "root");
+    assert_eq!(event["peer"], "127.0.0.1");
+    assert_eq!(event["size"], 4);
+    assert!(event["from"].is_null());
+    let real_path = server.root.join("a.txt");
+    assert_eq!(event["real_path"], real_path.to_str().unwrap());
+    assert!(event["real_from"].is_null());
+}
+
+#[tokio::test]
+async fn commands_are_told_real_paths() {
+    let mut log = None;
+    let server = TestServer::with_config(|config| {
+        let path = config.path.with_file_name("hooks.log");
+        config.hooks.run.push(shell(&format!(
+            "echo \"$KIRAFTP_PATH $KIRAFTP_REAL_PATH $KIRAFTP_REAL_FROM\" >> {}",
+            path.display()
+        )));
+        let user = UserConfig {
+            home: Some("/home/root".into()),
+            ..UserConfig::default()
+        };
+        config.users.insert(String::from("root"), user);
+        log = Some(path);
+    })
+    .await;
+    let log = log.unwrap();
+    let mut client = server.login().await;
+    client.stor("a.txt", b"data").await;
+    wait_for_lines(&log, 1).await;
+    client.expect_command("RNFR a.txt", "350").await;
+    client.expect_command("RNTO b.txt", "250").await;
+    let home = server.root.join("home/root");
+    assert_eq!(
+        wait_for_lines(&log, 2).await,
+        [
+            format!("/a.txt {} ", home.join("a.txt").display()),
+            format!(
+                "/b.txt {} {}",
+                home.join("b.txt").display(),
+                home.join("a.txt").display()
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn real_paths_are_left_out_for_memory_storage() {
+    let mut log = None;
+    let server = TestServer::with_config(|config| {
+        let path = config.path.with_file_name("hooks.log");
+        config.storage = Storage::Memory { quota: None };
+        config.hooks.run.push(shell(&format!(
+            "echo \"$KIRAFTP_PATH ${{KIRAFTP_REAL_PATH-unset}}\" >> {}",
+            path.display()
+        )));
+        log = Some(path);
+    })
+    .await;
+    let log = log.unwrap();
+    let mut client = server.login().await;
+    client.stor("a.txt", b"data").await;
+    assert_eq!(wait_for_lines(&log, 1).await, ["/a.txt unset"]);
+}
+
+#[tokio::test]
+async fn slow_hooks_are_killed() {
+    let mut log = None;
+    let server = TestServer::with_config(|config| {
+        let path = config.path.with_file_name("hooks.log");
+        config.hooks.timeout = 1;
+        config.hooks.max_running = 1;
+        config
+            .hooks
+            .run
+            .push(shell(&format!("sleep 10; echo late >> {}", path.display())));
+        config
+            .hooks
+            .run
+            .push(shell(&format!("echo next >> {}", path.display())));
+        log = Some(path);
+    })
+    .await;
+    let log = log.unwrap();
+    let mut client = server.login().await;
+    client.stor("a.txt", b"data").await;
+    // The second hook waits for the first to be given up on.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!log.exists());
+    assert_eq!(wait_for_lines(&log, 1).await, ["next"]);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), "next\n");
+}
+
+#[tokio::test]
+async fn hooks_past_the_queue_are_dropped() {
+    let mut log = None;
+    let server = TestServer::with_config(|config| {
+        let path = config.path.with_file_name("hooks.log");
+        config.hooks.timeout = 1;
+        config.hooks.max_running = 1;
+        config.hooks.max_queued = 0;
+        config.hooks.run.push(shell(&format!(
+            "echo first >> {}; sleep 10",
+            path.display()
+        )));
+        config
+            .hooks
+            .run
+            .push(shell(&format!("echo second >> {}", path.display())));
+        log = Some(path);
+    })
+    .await;
+    let log = log.unwrap();
+    let mut client = server.login().await;
+    client.stor("a.txt", b"data").await;
+    assert_eq!(wait_for_lines(&log, 1).await, ["first"]);
+    // The second hook had nowhere to wait, even once the first is killed.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), "first\n");
+}
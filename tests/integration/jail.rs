@@ -0,0 +1,129 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The served directory must behave as `/`, whatever path the client sends.
+
+use super::TestServer;
+
+fn outside(server: &TestServer) -> std::path::PathBuf {
+    server.root.parent().unwrap().to_path_buf()
+}
+
+#[tokio::test]
+async fn symlinks_cannot_leave_root() {
+    let server = TestServer::start().await;
+    let outside = outside(&server).join("outside");
+    std::fs::create_dir(&outside).unwrap();
+    std::fs::write(outside.join("secret.txt"), b"secret").unwrap();
+    std::os::unix::fs::symlink(&outside, server.root.join("dir")).unwrap();
+    std::os::unix::fs::symlink(outside.join("secret.txt"), server.root.join("file")).unwrap();
+    std::os::unix::fs::symlink(outside.join("new.txt"), server.root.join("dangling")).unwrap();
+    std::fs::write(server.root.join("inside.txt"), b"inside").unwrap();
+    std::os::unix::fs::symlink("inside.txt", server.root.join("near")).unwrap();
+    let mut client = server.login().await;
+    let _data_stream = client.pasv().await;
+    client.expect_command("RETR file", "550").await;
+    let _data_stream = client.pasv().await;
+    client.expect_command("RETR dir/secret.txt", "550").await;
+    let _data_stream = client.pasv().await;
+    client.expect_command("STOR dir/new.txt", "553").await;
+    let _data_stream = client.pasv().await;
+    client.expect_command("STOR dangling", "553").await;
+    assert!(!outside.join("new.txt").exists());
+    client.expect_command("DELE dir/secret.txt", "550").await;
+    client.expect_command("MKD dir/sub", "550").await;
+    client.expect_command("CWD dir", "550").await;
+    client.expect_command("RNFR dir/secret.txt", "550").await;
+    client.expect_command("RNFR inside.txt", "350").await;
+    client.expect_command("RNTO dir/moved.txt", "550").await;
+    assert_eq!(
+        std::fs::read(outside.join("secret.txt")).unwrap(),
+        b"secret"
+    );
+    assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 1);
+    client.expect_command("DELE file", "550").await;
+    assert!(outside.join("secret.txt").exists());
+    // Links that stay inside still work.
+    assert_eq!(client.retr("near").await, b"inside");
+}
+
+#[tokio::test]
+async fn cwd_cannot_leave_root() {
+    let server = TestServer::start().await;
+    std::fs::create_dir(server.root.join("sub")).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("CWD ..", "250").await;
+    client.expect_command("PWD", "257 \"/\"").await;
+    client.expect_command("CWD sub/../../..", "250").await;
+    client.expect_command("PWD", "257 \"/\"").await;
+    client.expect_command("CWD /sub", "250").await;
+    client.expect_command("CWD ../..", "250").await;
+    client.expect_command("PWD", "257 \"/\"").await;
+}
+
+#[tokio::test]
+async fn retr_cannot_leave_root() {
+    let server = TestServer::start().await;
+    std::fs::write(outside(&server).join("secret.txt"), b"secret").unwrap();
+    std::fs::write(server.root.join("secret.txt"), b"public").unwrap();
+    let mut client = server.login().await;
+    assert_eq!(client.retr("../secret.txt").await, b"public");
+    client.expect_command("CWD ..", "250").await;
+    assert_eq!(client.retr("secret.txt").await, b"public");
+    let absolute = outside(&server).join("secret.txt");
+    client.expect_command("PASV", "227").await;
+    client
+        .expect_command(&format!("RETR {}", absolute.display()), "550")
+        .await;
+}
+
+#[tokio::test]
+async fn stor_cannot_leave_root() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    client.stor("../escaped.txt", b"data").await;
+    assert!(!outside(&server).join("escaped.txt").exists());
+    assert_eq!(
+        std::fs::read(server.root.join("escaped.txt")).unwrap(),
+        b"data"
+    );
+}
+
+#[tokio::test]
+async fn list_cannot_leave_root() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("inside.txt"), b"").unwrap();
+    let mut client = server.login().await;
+    for path in ["..", "/../.."] {
+        let listing = client.list(path).await;
+        let names: Vec<&str> = listing
+            .lines()
+            .map(|x| x.rsplit(' ').next().unwrap())
+            .collect();
+        assert_eq!(names, ["inside.txt"], "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn changes_cannot_leave_root() {
+    let server = TestServer::start().await;
+    std::fs::write(outside(&server).join("victim.txt"), b"victim").unwrap();
+    std::fs::write(server.root.join("a.txt"), b"a").unwrap();
+    let mut client = server.login().await;
+    client.expect_command("DELE ../victim.txt", "550").await;
+    client
+        .expect_command("MKD ../../made", "257 \"/made\"")
+        .await;
+    client.expect_command("RNFR a.txt", "350").await;
+    client
+        .expect_command("RNTO ./../../sub/../b.txt", "250")
+        .await;
+    assert_eq!(
+        std::fs::read(outside(&server).join("victim.txt")).unwrap(),
+        b"victim"
+    );
+    assert!(server.root.join("made").is_dir());
+    assert!(!outside(&server).join("made").exists());
+    assert!(server.root.join("b.txt").exists());
+    assert!(!outside(&server).join("b.txt").exists());
+}
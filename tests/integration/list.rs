@@ -0,0 +1,56 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::TestServer;
+use tokio::io::AsyncReadExt;
+
+fn names(listing: &str) -> Vec<&str> {
+    let mut names: Vec<&str> = listing
+        .lines()
+        .map(|line| line.rsplit(' ').next().unwrap())
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+#[tokio::test]
+async fn list_passive() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"hello").unwrap();
+    std::fs::create_dir(server.root.join("dir")).unwrap();
+    let mut client = server.login().await;
+    let listing = client.list("").await;
+    assert_eq!(names(&listing), ["a.txt", "dir"]);
+    let file = listing.lines().find(|x| x.ends_with("a.txt")).unwrap();
+    assert!(file.starts_with("-rw"), "{}", file);
+    assert!(file.contains(" 5 "), "{}", file);
+    let dir = listing.lines().find(|x| x.ends_with("dir")).unwrap();
+    assert!(dir.starts_with('d'), "{}", dir);
+}
+
+#[tokio::test]
+async fn list_active() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"hello").unwrap();
+    let mut client = server.login().await;
+    let listener = client.port().await;
+    client.send(b"LIST\r\n").await;
+    let (mut data_stream, _) = listener.accept().await.unwrap();
+    let mut listing = String::new();
+    data_stream.read_to_string(&mut listing).await.unwrap();
+    client.expect("150").await;
+    client.expect("226").await;
+    assert_eq!(names(&listing), ["a.txt"]);
+}
+
+#[tokio::test]
+async fn list_subdirectory_ignoring_options() {
+    let server = TestServer::start().await;
+    std::fs::create_dir(server.root.join("dir")).unwrap();
+    std::fs::write(server.root.join("dir/b.txt"), b"").unwrap();
+    let mut client = server.login().await;
+    assert_eq!(names(&client.list("dir").await), ["b.txt"]);
+    assert_eq!(names(&client.list("-la dir").await), ["b.txt"]);
+    client.expect_command("CWD dir", "250").await;
+    assert_eq!(names(&client.list("-a").await), ["b.txt"]);
+}
@@ -0,0 +1,131 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{config, temp_dir, Client};
+use kiraftp::{
+    utils::{
+        config::{Config, LogConfig, LogOutput},
+        log,
+    },
+    Server,
+};
+use slog::{info, o, Drain, Key, Logger, OwnedKVList, Record, KV};
+use std::{
+    fmt::{self, Write},
+    sync::{Arc, Mutex},
+};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Keeps each record as its message followed by ` key=value` pairs.
+#[derive(Clone, Default)]
+struct Records(Arc<Mutex<Vec<String>>>);
+
+struct Line<'a>(&'a mut String);
+
+impl slog::Serializer for Line<'_> {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        write!(self.0, " {}={}", key, val).unwrap();
+        Ok(())
+    }
+}
+
+impl Drain for Records {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), slog::Never> {
+        let mut line = record.msg().to_string();
+        record.kv().serialize(record, &mut Line(&mut line)).unwrap();
+        values.serialize(record, &mut Line(&mut line)).unwrap();
+        self.0.lock().unwrap().push(line);
+        Ok(())
+    }
+}
+
+impl Records {
+    /// The first record with `message` that carries `context`.
+    fn find(&self, message: &str, context: &str) -> String {
+        let records = self.0.lock().unwrap();
+        records
+            .iter()
+            .find(|x| x.starts_with(message) && x.contains(context))
+            .unwrap_or_else(|| panic!("no {:?} in {:#?}", message, records))
+            .clone()
+    }
+}
+
+#[tokio::test]
+async fn sessions_log_with_context() {
+    let records = Records::default();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = config(temp_dir());
+    let server = Server::builder()
+        .config(config)
+        .listener(listener)
+        .logger(Logger::root(records.clone(), o!()))
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(server.run());
+    let mut first = Client::new(TcpStream::connect(addr).await.unwrap()).await;
+    let mut second = Client::new(TcpStream::connect(addr).await.unwrap()).await;
+    let peer = second.local_addr();
+    second.expect_command("USER root", "331").await;
+    second.expect_command("pass wrong", "530").await;
+    second.login("root", "password").await;
+    second.expect_command("NOOP", "200").await;
+    first.expect_command("NOOP", "200").await;
+
+    let peer = format!("peer={}", peer);
+    let established = records.find("Connection from 127.0.0.1 was", &peer);
+    assert!(established.contains("session_id=2"));
+    let failed = records.find("Login failed for root.", &peer);
+    assert!(!failed.contains("user="));
+    let logged_in = records.find("Logged in.", &peer);
+    assert!(logged_in.contains("user=root"));
+    assert!(logged_in.contains("session_id=2"));
+    let all = records.0.lock().unwrap();
+    assert!(all
+        .iter()
+        .any(|x| x.starts_with("Receive command: PASS ****")));
+    assert!(!all
+        .iter()
+        .any(|x| x.contains("wrong") || x.contains("PASS password")));
+}
+
+#[test]
+fn json_output_appends_lines() {
+    let path = temp_dir().join("kiraftp.log");
+    let config = LogConfig {
+        level: String::from("info"),
+        output: LogOutput::Json { path: path.clone() },
+    };
+    for message in ["first", "second"] {
+        let (logger, guard) = log::build(&config).unwrap();
+        info!(logger, "{}", message; "user" => "root");
+        slog::debug!(logger, "filtered");
+        drop(logger);
+        drop(guard);
+    }
+    let content = std::fs::read_to_string(path).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", content);
+    assert!(lines[0].contains("\"msg\":\"first\""), "{}", lines[0]);
+    assert!(lines[0].contains("\"user\":\"root\""), "{}", lines[0]);
+    assert!(lines[1].contains("\"msg\":\"second\""), "{}", lines[1]);
+}
+
+#[test]
+fn log_settings_are_validated() {
+    let config: Config = serde_yaml::from_str(
+        "username: a\npassword: b\npath: /\nlog:\n  level: loud\n  output:\n    type: syslog\n    \
+         facility: local9\n",
+    )
+    .unwrap();
+    let problems = config.validate().unwrap_err();
+    assert!(problems.iter().any(|x| x.starts_with("log.level:")));
+    assert!(problems.iter().any(|x| x.starts_with("log.facility:")));
+    assert_eq!(log::facility("local3"), Some(19));
+    assert_eq!(log::facility("daemon"), Some(3));
+}
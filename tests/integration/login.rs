@@ -0,0 +1,67 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::TestServer;
+
+#[tokio::test]
+async fn login_with_configured_user() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.login("root", "password").await;
+    client.expect_command("PWD", "257 \"/\"").await;
+    client.expect_command("QUIT", "221").await;
+}
+
+#[tokio::test]
+async fn login_with_wrong_password() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.expect_command("USER root", "331").await;
+    client.expect_command("PASS wrong", "530").await;
+    client.expect_command("PWD", "530").await;
+}
+
+#[tokio::test]
+async fn login_with_unknown_user() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.expect_command("USER nobody", "331").await;
+    client.expect_command("PASS password", "530").await;
+}
+
+#[tokio::test]
+async fn login_anonymous() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    client.login("anonymous", "guest@example.com").await;
+    client.expect_command("PWD", "257").await;
+}
+
+#[tokio::test]
+async fn cannot_change_user_after_login() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    client.expect_command("USER anonymous", "530").await;
+    client.expect_command("PASS password", "230 Already").await;
+}
+
+#[tokio::test]
+async fn commands_require_login() {
+    let server = TestServer::start().await;
+    let mut client = server.client().await;
+    for command in [
+        "PWD",
+        "CWD /",
+        "PASV",
+        "PORT 127,0,0,1,4,1",
+        "LIST",
+        "RETR file",
+        "STOR file",
+    ] {
+        client.expect_command(command, "530").await;
+    }
+    // Commands that don't touch the filesystem work before login.
+    client.expect_command("NOOP", "200").await;
+    client.expect_command("SYST", "215").await;
+    client.expect_command("TYPE I", "200").await;
+}
@@ -0,0 +1,301 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! An in-process server and a scripted FTP client for integration tests.
+
+mod atomic;
+mod audit;
+mod bounce;
+mod cli;
+mod config;
+mod errors;
+mod filter;
+mod hash;
+mod home;
+mod hooks;
+mod jail;
+mod list;
+mod logging;
+mod login;
+mod manage;
+mod memory;
+mod metrics;
+mod passive;
+mod pipelining;
+mod quota;
+mod reload;
+#[cfg(feature = "s3")]
+mod s3;
+mod server;
+mod storage;
+mod transfer;
+mod upload_size;
+mod xferlog;
+
+use kiraftp::{
+    utils::{
+        config::Config,
+        net::{parse_ipv4_addr, print_ipv4_addr},
+    },
+    ReloadHandle, Server, ShutdownHandle,
+};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    task::JoinHandle,
+};
+
+/// Create an empty directory that is unique to this test.
+pub fn temp_dir() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "kiraftp-test-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::create_dir_all(&path).unwrap();
+    path.canonicalize().unwrap()
+}
+
+/// A config serving `path` to `root` with password `password`.
+pub fn config(path: PathBuf) -> Config {
+    Config {
+        username: String::from("root"),
+        password: String::from("password"),
+        path,
+        ..Config::default()
+    }
+}
+
+/// A server listening on an ephemeral loopback port and serving `root`,
+/// which sits inside a private temporary directory.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub root: PathBuf,
+    pub config: Config,
+    pub metrics: Option<SocketAddr>,
+    pub shutdown: ShutdownHandle,
+    pub reload: ReloadHandle,
+    handle: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::with_config(|_| {}).await
+    }
+
+    pub async fn with_config(customize: impl FnOnce(&mut Config)) -> Self {
+        let root = temp_dir().join("root");
+        std::fs::create_dir(&root).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut config = Config {
+            listen: addr.ip(),
+            port: addr.port(),
+            ..config(root.clone())
+        };
+        customize(&mut config);
+        let server = Server::builder()
+            .config(config.clone())
+            .listener(listener)
+            .build()
+            .await
+            .unwrap();
+        let shutdown = server.shutdown_handle();
+        let reload = server.reload_handle();
+        let metrics = server.metrics_addr();
+        let handle = tokio::spawn(server.run());
+        Self {
+            addr,
+            root,
+            config,
+            metrics,
+            shutdown,
+            reload,
+            handle,
+        }
+    }
+
+    pub async fn client(&self) -> Client {
+        Client::new(TcpStream::connect(self.addr).await.unwrap()).await
+    }
+
+    /// Shut down and wait for the server to finish draining sessions.
+    pub async fn stop(&mut self) {
+        self.shutdown.shutdown();
+        (&mut self.handle).await.unwrap();
+    }
+
+    /// Connect and log in with the default credentials.
+    pub async fn login(&self) -> Client {
+        let mut client = self.client().await;
+        client.login("root", "password").await;
+        client
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        self.handle.abort();
+        if let Some(parent) = self.root.parent() {
+            let _ = std::fs::remove_dir_all(parent);
+        }
+    }
+}
+
+/// The client side of a control connection.
+pub struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    /// Wrap a connected control stream and consume the greeting.
+    pub async fn new(stream: TcpStream) -> Self {
+        stream.set_nodelay(true).unwrap();
+        let (reader, writer) = stream.into_split();
+        let mut client = Self {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        client.expect("220").await;
+        client
+    }
+
+    /// The client side address of the control connection.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.writer.local_addr().unwrap()
+    }
+
+    pub async fn send(&mut self, data: &[u8]) {
+        self.writer.write_all(data).await.unwrap();
+    }
+
+    /// Read one reply, following multi-line replies to their last line.
+    pub async fn reply(&mut self) -> String {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).await.unwrap();
+            assert!(line.ends_with("\r\n"), "truncated reply: {:?}", line);
+            line.truncate(line.len() - 2);
+            let last = match reply.get(..3) {
+                Some(code) => line.starts_with(code) && line.as_bytes().get(3) == Some(&b' '),
+                None => line.as_bytes().get(3) != Some(&b'-'),
+            };
+            reply.push_str(&line);
+            if last {
+                return reply;
+            }
+            reply.push('\n');
+        }
+    }
+
+    pub async fn expect(&mut self, code: &str) -> String {
+        let reply = self.reply().await;
+        assert!(
+            reply.starts_with(code),
+            "expected {}, got {:?}",
+            code,
+            reply
+        );
+        reply
+    }
+
+    /// Send a single command and return its reply.
+    pub async fn command(&mut self, command: &str) -> String {
+        self.send(format!("{}\r\n", command).as_bytes()).await;
+        self.reply().await
+    }
+
+    /// Send a single command and check the reply starts with `expected`.
+    pub async fn expect_command(&mut self, command: &str, expected: &str) -> String {
+        let reply = self.command(command).await;
+        assert!(
+            reply.starts_with(expected),
+            "{}: expected {}, got {:?}",
+            command,
+            expected,
+            reply
+        );
+        reply
+    }
+
+    pub async fn login(&mut self, username: &str, password: &str) {
+        self.expect_command(&format!("USER {}", username), "331")
+            .await;
+        self.expect_command(&format!("PASS {}", password), "230")
+            .await;
+    }
+
+    /// Enter passive mode and open the data connection.
+    pub async fn pasv(&mut self) -> TcpStream {
+        let reply = self.expect_command("PASV", "227").await;
+        let addr = &reply[reply.find('(').unwrap() + 1..reply.find(')').unwrap()];
+        TcpStream::connect(parse_ipv4_addr(addr).unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Enter active mode with a fresh loopback listener.
+    pub async fn port(&mut self) -> TcpListener {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = print_ipv4_addr(listener.local_addr().unwrap());
+        self.expect_command(&format!("PORT {}", &addr[1..addr.len() - 1]), "200")
+            .await;
+        listener
+    }
+
+    /// Run `command` over a passive data connection and collect what the
+    /// server sends.
+    pub async fn download(&mut self, command: &str) -> Vec<u8> {
+        let mut data_stream = self.pasv().await;
+        self.send(format!("{}\r\n", command).as_bytes()).await;
+        self.expect("150").await;
+        let mut data = Vec::new();
+        data_stream.read_to_end(&mut data).await.unwrap();
+        self.expect("226").await;
+        data
+    }
+
+    /// Run `command` over a passive data connection and send `data`.
+    pub async fn upload(&mut self, command: &str, data: &[u8]) {
+        let mut data_stream = self.pasv().await;
+        self.send(format!("{}\r\n", command).as_bytes()).await;
+        self.expect("150").await;
+        data_stream.write_all(data).await.unwrap();
+        drop(data_stream);
+        self.expect("226").await;
+    }
+
+    pub async fn retr(&mut self, path: &str) -> Vec<u8> {
+        self.download(&format!("RETR {}", path)).await
+    }
+
+    pub async fn stor(&mut self, path: &str, data: &[u8]) {
+        self.upload(&format!("STOR {}", path), data).await
+    }
+
+    pub async fn list(&mut self, path: &str) -> String {
+        let command = if path.is_empty() {
+            String::from("LIST")
+        } else {
+            format!("LIST {}", path)
+        };
+        String::from_utf8(self.download(&command).await).unwrap()
+    }
+}
+
+/// A spread of byte values large enough to span several transfer buffers.
+pub fn sample_data(len: usize) -> Vec<u8> {
+    (0..len).map(|x| (x % 251) as u8).collect()
+}
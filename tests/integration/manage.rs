@@ -0,0 +1,54 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::TestServer;
+
+#[tokio::test]
+async fn make_and_remove_directory() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    client.expect_command("MKD new", "257 \"/new\"").await;
+    assert!(server.root.join("new").is_dir());
+    client.expect_command("MKD new", "550").await;
+    client.expect_command("CWD new", "250").await;
+    client
+        .expect_command("MKD ../other", "257 \"/other\"")
+        .await;
+    client.expect_command("RMD /new", "250").await;
+    assert!(!server.root.join("new").exists());
+    client.expect_command("RMD /", "550").await;
+}
+
+#[tokio::test]
+async fn remove_directory_requires_empty_directory() {
+    let server = TestServer::start().await;
+    std::fs::create_dir(server.root.join("dir")).unwrap();
+    std::fs::write(server.root.join("dir/a.txt"), b"").unwrap();
+    let mut client = server.login().await;
+    client.expect_command("RMD dir", "550").await;
+    client.expect_command("RMD dir/a.txt", "550").await;
+    client.expect_command("DELE dir", "550").await;
+    client.expect_command("DELE dir/a.txt", "250").await;
+    client.expect_command("DELE dir/a.txt", "550").await;
+    client.expect_command("RMD dir", "250").await;
+}
+
+#[tokio::test]
+async fn rename() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"data").unwrap();
+    std::fs::create_dir(server.root.join("dir")).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("RNTO b.txt", "503").await;
+    client.expect_command("RNFR missing.txt", "550").await;
+    client.expect_command("RNTO b.txt", "503").await;
+    client.expect_command("RNFR a.txt", "350").await;
+    client.expect_command("RNTO dir/b.txt", "250").await;
+    assert_eq!(
+        std::fs::read(server.root.join("dir/b.txt")).unwrap(),
+        b"data"
+    );
+    client.expect_command("RNFR dir", "350").await;
+    client.expect_command("RNTO ../../moved", "250").await;
+    assert!(server.root.join("moved/b.txt").is_file());
+}
@@ -0,0 +1,124 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{config, sample_data, temp_dir, Client, TestServer};
+use kiraftp::{
+    storage::{MemoryFileSystem, StorageBackend},
+    utils::config::{Config, Storage},
+    Server,
+};
+use std::{path::Path, sync::Arc};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+};
+
+async fn memory_server(quota: Option<u64>) -> TestServer {
+    TestServer::with_config(|config| config.storage = Storage::Memory { quota }).await
+}
+
+#[test]
+fn storage_from_yaml() {
+    let config: Config = serde_yaml::from_str(
+        "listen: 127.0.0.1\nport: 21\nusername: a\npassword: b\npath: /srv\n\
+         storage:\n  type: memory\n  quota: 1024\n",
+    )
+    .unwrap();
+    assert_eq!(config.storage, Storage::Memory { quota: Some(1024) });
+    let config: Config =
+        serde_yaml::from_str("listen: 127.0.0.1\nport: 21\nusername: a\npassword: b\npath: /srv\n")
+            .unwrap();
+    assert_eq!(config.storage, Storage::Local);
+}
+
+#[tokio::test]
+async fn files_stay_in_memory() {
+    let server = memory_server(None).await;
+    let content = sample_data(100_000);
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.expect_command("MKD dir", "257").await;
+    client.stor("dir/data.bin", &content).await;
+    assert_eq!(client.retr("/dir/data.bin").await, content);
+    let listing = client.list("dir").await;
+    assert!(
+        listing.starts_with("-rw-r--r-- 1 ftp ftp 100000 "),
+        "{}",
+        listing
+    );
+    assert!(listing.ends_with(" data.bin\r\n"), "{}", listing);
+    client.expect_command("RNFR dir", "350").await;
+    client.expect_command("RNTO moved", "250").await;
+    assert_eq!(client.retr("moved/data.bin").await, content);
+    client.expect_command("RMD moved", "550").await;
+    client.expect_command("DELE moved/data.bin", "250").await;
+    client.expect_command("RMD moved", "250").await;
+    assert_eq!(std::fs::read_dir(&server.root).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn quota_is_enforced() {
+    let server = memory_server(Some(1000)).await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("a.bin", &sample_data(600)).await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"STOR b.bin\r\n").await;
+    client.expect("150").await;
+    let _ = tokio::io::AsyncWriteExt::write_all(&mut data_stream, &sample_data(600)).await;
+    drop(data_stream);
+    client.expect("426").await;
+    // Replacing a file frees its space first.
+    client.stor("a.bin", &sample_data(900)).await;
+}
+
+#[tokio::test]
+async fn permissions_are_enforced() {
+    let storage = Arc::new(MemoryFileSystem::new(None));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder()
+        .config(config(temp_dir()))
+        .listener(listener)
+        .storage(storage.clone())
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(server.run());
+    let mut client = Client::new(TcpStream::connect(addr).await.unwrap()).await;
+    client.login("root", "password").await;
+    client.expect_command("MKD locked", "257").await;
+    client.stor("locked/a.txt", b"data").await;
+    storage.set_permissions("/locked", 0o555).unwrap();
+    let _data_stream = client.pasv().await;
+    client.expect_command("STOR locked/b.txt", "553").await;
+    client.expect_command("DELE locked/a.txt", "550").await;
+    assert!(client.list("").await.starts_with("dr-xr-xr-x"));
+    storage.set_permissions("/locked/a.txt", 0o200).unwrap();
+    client.expect_command("PASV", "227").await;
+    client.expect_command("RETR locked/a.txt", "550").await;
+    assert_eq!(storage.used(), 4);
+}
+
+#[tokio::test]
+async fn open_files_count_after_rename_and_remove() {
+    let storage = MemoryFileSystem::new(Some(100));
+    let mut file = storage.open_write(Path::new("/a.bin"), 0).await.unwrap();
+    file.write_all(&[0; 40]).await.unwrap();
+    storage
+        .rename(Path::new("/a.bin"), Path::new("/b.bin"))
+        .await
+        .unwrap();
+    file.write_all(&[0; 40]).await.unwrap();
+    assert_eq!(storage.used(), 80);
+    assert_eq!(storage.stat(Path::new("/b.bin")).await.unwrap().size, 80);
+    storage.remove(Path::new("/b.bin")).await.unwrap();
+    assert_eq!(storage.used(), 0);
+    file.write_all(&[0; 60]).await.unwrap();
+    assert_eq!(storage.used(), 60);
+    // Still held by the writer, so not free for other files.
+    let mut other = storage.open_write(Path::new("/c.bin"), 0).await.unwrap();
+    assert!(other.write_all(&[0; 60]).await.is_err());
+    drop(file);
+    assert_eq!(storage.used(), 0);
+}
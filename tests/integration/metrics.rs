@@ -0,0 +1,101 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{sample_data, TestServer};
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(format!("GET {} HTTP/1.1\r\nHost: kiraftp\r\n\r\n", path).as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+fn has_line(metrics: &str, line: &str) -> bool {
+    metrics.lines().any(|x| x == line)
+}
+
+#[tokio::test]
+async fn metrics_are_served() {
+    let server = TestServer::with_config(|config| {
+        config.metrics = Some("127.0.0.1:0".parse().unwrap());
+    })
+    .await;
+    let addr = server.metrics.unwrap();
+    let mut client = server.client().await;
+    client.expect_command("USER root", "331").await;
+    client.expect_command("PASS wrong", "530").await;
+    client.login("root", "password").await;
+    client.expect_command("NOOP", "200").await;
+    client.expect_command("XYZZY", "500").await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("a.bin", &sample_data(1000)).await;
+    client.retr("a.bin").await;
+    let data_stream = client.pasv().await;
+
+    let response = get(addr, "/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    for line in [
+        "kiraftp_sessions 1",
+        "kiraftp_passive_ports 1",
+        "kiraftp_logins_total{result=\"failure\"} 1",
+        "kiraftp_logins_total{result=\"success\"} 1",
+        "kiraftp_commands_total{code=\"530\",verb=\"PASS\"} 1",
+        "kiraftp_commands_total{code=\"230\",verb=\"PASS\"} 1",
+        "kiraftp_commands_total{code=\"200\",verb=\"NOOP\"} 1",
+        "kiraftp_commands_total{code=\"500\",verb=\"OTHER\"} 1",
+        "kiraftp_commands_total{code=\"226\",verb=\"STOR\"} 1",
+        "kiraftp_commands_total{code=\"227\",verb=\"PASV\"} 3",
+        "kiraftp_transfer_bytes_total{direction=\"upload\"} 1000",
+        "kiraftp_transfer_bytes_total{direction=\"download\"} 1000",
+        "kiraftp_transfer_duration_seconds_count{direction=\"download\"} 1",
+    ] {
+        assert!(has_line(&response, line), "no {:?} in {}", line, response);
+    }
+
+    client.expect_command("QUIT", "221").await;
+    drop(data_stream);
+    drop(client);
+    let mut metrics = String::new();
+    for _ in 0..50 {
+        metrics = get(addr, "/metrics").await;
+        if has_line(&metrics, "kiraftp_sessions 0") {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(has_line(&metrics, "kiraftp_sessions 0"), "{}", metrics);
+    assert!(has_line(&metrics, "kiraftp_passive_ports 0"), "{}", metrics);
+    assert!(has_line(
+        &metrics,
+        "kiraftp_commands_total{code=\"221\",verb=\"QUIT\"} 1"
+    ));
+}
+
+#[tokio::test]
+async fn other_paths_are_not_found() {
+    let server = TestServer::with_config(|config| {
+        config.metrics = Some("127.0.0.1:0".parse().unwrap());
+    })
+    .await;
+    let response = get(server.metrics.unwrap(), "/").await;
+    assert!(
+        response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+        "{}",
+        response
+    );
+}
+
+#[tokio::test]
+async fn metrics_are_off_by_default() {
+    let server = TestServer::start().await;
+    assert!(server.metrics.is_none());
+}
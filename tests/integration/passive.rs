@@ -0,0 +1,92 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::TestServer;
+use kiraftp::utils::{config::PortRange, net::parse_ipv4_addr};
+use std::{net::SocketAddr, time::Duration};
+use tokio::net::TcpStream;
+
+fn passive_addr(reply: &str) -> SocketAddr {
+    let addr = &reply[reply.find('(').unwrap() + 1..reply.find(')').unwrap()];
+    parse_ipv4_addr(addr).unwrap()
+}
+
+#[tokio::test]
+async fn pasv_replaces_the_previous_port() {
+    let server = TestServer::with_config(|config| config.max_passive_ports = Some(1)).await;
+    let mut client = server.login().await;
+    let first = passive_addr(&client.expect_command("PASV", "227").await);
+    let second = passive_addr(&client.expect_command("PASV", "227").await);
+    assert_ne!(first, second);
+    assert!(TcpStream::connect(first).await.is_err());
+    assert!(client.list("").await.is_empty());
+}
+
+#[tokio::test]
+async fn passive_ports_are_capped_across_sessions() {
+    let server = TestServer::with_config(|config| config.max_passive_ports = Some(1)).await;
+    let mut first = server.login().await;
+    let mut second = server.login().await;
+    first.expect_command("PASV", "227").await;
+    second.expect_command("PASV", "425").await;
+    first.expect_command("QUIT", "221").await;
+    let addr = passive_addr(&second.expect_command("PASV", "227").await);
+    let _data_stream = TcpStream::connect(addr).await.unwrap();
+    second.expect_command("LIST", "150").await;
+    second.expect("226").await;
+    // The port is given back once the data connection is made.
+    second.expect_command("PASV", "227").await;
+}
+
+#[tokio::test]
+async fn passive_ports_come_from_the_range() {
+    let range = PortRange {
+        min: 47000,
+        max: 47002,
+    };
+    let server = TestServer::with_config(|config| config.passive_ports = Some(range.clone())).await;
+    let mut clients = Vec::new();
+    let mut ports = Vec::new();
+    for _ in 0..3 {
+        let mut client = server.login().await;
+        let port = passive_addr(&client.expect_command("PASV", "227").await).port();
+        assert!((range.min..=range.max).contains(&port));
+        assert!(!ports.contains(&port));
+        ports.push(port);
+        clients.push(client);
+    }
+    let mut client = server.login().await;
+    client.expect_command("PASV", "425").await;
+    clients[1].expect_command("QUIT", "221").await;
+    let port = passive_addr(&client.expect_command("PASV", "227").await).port();
+    assert_eq!(port, ports[1]);
+}
+
+#[tokio::test]
+async fn unused_passive_ports_time_out() {
+    let server = TestServer::with_config(|config| config.passive_timeout = 1).await;
+    let mut client = server.login().await;
+    let addr = passive_addr(&client.expect_command("PASV", "227").await);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(TcpStream::connect(addr).await.is_err());
+    client
+        .expect_command("LIST", "425 Use PORT or PASV first")
+        .await;
+    // A transfer that never gets its connection gives up too.
+    client.expect_command("PASV", "227").await;
+    client.send(b"LIST\r\n").await;
+    client.expect("425 Can't open data connection").await;
+}
+
+#[tokio::test]
+async fn expiry_keeps_discarding_an_overlong_line() {
+    let server = TestServer::with_config(|config| config.passive_timeout = 1).await;
+    let mut client = server.login().await;
+    client.expect_command("PASV", "227").await;
+    client.send(&[b'a'; 1100]).await;
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    // The end of the overlong line, not a command of its own.
+    client.send(b"SYST\r\n").await;
+    client.expect("500").await;
+    client.expect_command("SYST", "215").await;
+}
@@ -0,0 +1,117 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{config, sample_data, temp_dir, Client};
+use kiraftp::{storage::LocalFileSystem, utils::config::Config, FTPSession};
+use slog::{o, Discard, Logger};
+use std::{net::IpAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// Run a session over a loopback socket pair and return the client side.
+async fn connect(root: PathBuf) -> (Client, JoinHandle<tokio::io::Result<()>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+    let config = Config {
+        listen: IpAddr::from([127, 0, 0, 1]),
+        ..config(root)
+    };
+    let storage = Arc::new(LocalFileSystem::new(&config.path));
+    let mut session = FTPSession::new(
+        stream,
+        Arc::new(Logger::root(Discard, o!())),
+        Arc::new(config),
+        storage,
+    );
+    let session = tokio::spawn(async move { session.run().await });
+    (Client::new(client).await, session)
+}
+
+#[tokio::test]
+async fn pipelined_commands_are_answered_in_order() {
+    let (mut client, session) = connect(temp_dir()).await;
+    client
+        .send(b"USER root\r\nPASS password\r\nTYPE I\r\nNOOP\r\nSYST\r\nQUIT\r\n")
+        .await;
+    client.expect("331").await;
+    client.expect("230").await;
+    client.expect("200 Switching to Binary").await;
+    client.expect("200 NOOP").await;
+    client.expect("215").await;
+    client.expect("221").await;
+    session.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn command_split_across_reads() {
+    let (mut client, _session) = connect(temp_dir()).await;
+    for part in [
+        &b"US"[..],
+        b"ER root\r",
+        b"\nPASS pass",
+        b"word\r\nNOOP\r\n",
+    ] {
+        client.send(part).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    client.expect("331").await;
+    client.expect("230").await;
+    client.expect("200").await;
+}
+
+#[tokio::test]
+async fn overlong_command_is_discarded_whole() {
+    let (mut client, _session) = connect(temp_dir()).await;
+    let mut command = b"NOOP ".to_vec();
+    command.resize(3000, b'x');
+    command.extend_from_slice(b"\r\nNOOP\r\n");
+    client.send(&command).await;
+    client.expect("500").await;
+    client.expect("200").await;
+    // A line just below the limit is still a command.
+    let mut command = b"USER ".to_vec();
+    // The longest accepted line is 1024 bytes, CRLF included.
+    command.resize(1022, b'x');
+    command.extend_from_slice(b"\r\nNOOP\r\n");
+    client.send(&command).await;
+    client.expect("331").await;
+    client.expect("200").await;
+}
+
+#[tokio::test]
+async fn overlong_command_with_split_crlf() {
+    let (mut client, _session) = connect(temp_dir()).await;
+    let mut command = b"NOOP ".to_vec();
+    command.resize(2000, b'x');
+    command.push(b'\r');
+    client.send(&command).await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    client.send(b"\nNOOP\r\n").await;
+    client.expect("500").await;
+    client.expect("200").await;
+}
+
+#[tokio::test]
+async fn retr_followed_by_pipelined_commands() {
+    let root = temp_dir();
+    let content = sample_data(100_000);
+    std::fs::write(root.join("data.bin"), &content).unwrap();
+    let (mut client, _session) = connect(root).await;
+    client.login("root", "password").await;
+    client.expect_command("TYPE I", "200").await;
+    let mut data = client.pasv().await;
+    client.send(b"RETR data.bin\r\nNOOP\r\nPWD\r\n").await;
+    let mut received = Vec::new();
+    data.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, content);
+    client.expect("150").await;
+    client.expect("226").await;
+    client.expect("200").await;
+    client.expect("257").await;
+}
@@ -0,0 +1,185 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{sample_data, TestServer};
+use kiraftp::utils::config::{QuotaConfig, Storage, UserConfig};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+async fn quota_server(bytes: Option<u64>, files: Option<u64>) -> TestServer {
+    TestServer::with_config(|config| {
+        let user = UserConfig {
+            home: Some("/home/root".into()),
+            quota: Some(QuotaConfig { bytes, files }),
+            ..UserConfig::default()
+        };
+        config.users.insert(String::from("root"), user);
+    })
+    .await
+}
+
+#[tokio::test]
+async fn byte_quota_stops_uploads() {
+    let server = quota_server(Some(1000), None).await;
+    let home = server.root.join("home/root");
+    std::fs::create_dir_all(home.join("dir")).unwrap();
+    std::fs::write(home.join("dir/old.bin"), sample_data(400)).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("a.bin", &sample_data(500)).await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"STOR b.bin\r\n").await;
+    client.expect("150").await;
+    let _ = data_stream.write_all(&sample_data(200)).await;
+    drop(data_stream);
+    client.expect("552").await;
+    assert!(!home.join("b.bin").exists());
+    let reply = client.expect_command("SITE QUOTA", "200").await;
+    assert!(reply.contains("Bytes: 900 of 1000 used."), "{}", reply);
+    assert!(reply.contains("Files: 2 of unlimited used."), "{}", reply);
+    // Replacing or deleting a file frees its space.
+    client.stor("a.bin", &sample_data(600)).await;
+    client.expect_command("DELE dir/old.bin", "250").await;
+    client.stor("b.bin", &sample_data(300)).await;
+    let reply = client.expect_command("SITE QUOTA", "200").await;
+    assert!(reply.contains("Bytes: 900 of 1000 used."), "{}", reply);
+}
+
+#[tokio::test]
+async fn stopped_appends_are_undone() {
+    let server = quota_server(Some(1000), None).await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("a.bin", &sample_data(600)).await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"APPE a.bin\r\n").await;
+    client.expect("150").await;
+    // The first part fits.
+    data_stream.write_all(&sample_data(300)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _ = data_stream.write_all(&sample_data(300)).await;
+    drop(data_stream);
+    client.expect("552").await;
+    assert_eq!(
+        std::fs::read(server.root.join("home/root/a.bin")).unwrap(),
+        sample_data(600)
+    );
+    let reply = client.expect_command("SITE QUOTA", "200").await;
+    assert!(reply.contains("Bytes: 600 of 1000 used."), "{}", reply);
+}
+
+/// Fail an upload of `path` part way, by filling up the memory storage.
+async fn fail_upload(client: &mut super::Client, path: &str) {
+    let mut data_stream = client.pasv().await;
+    client.send(format!("STOR {}\r\n", path).as_bytes()).await;
+    client.expect("150").await;
+    data_stream.write_all(&sample_data(200)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _ = data_stream.write_all(&sample_data(2000)).await;
+    drop(data_stream);
+    client.expect("426").await;
+}
+
+#[tokio::test]
+async fn failed_uploads_count_what_was_stored() {
+    for atomic_uploads in [false, true] {
+        let server = TestServer::with_config(|config| {
+            config.storage = Storage::Memory { quota: Some(1000) };
+            config.atomic_uploads = atomic_uploads;
+            let user = UserConfig {
+                home: Some("/home".into()),
+                quota: Some(QuotaConfig {
+                    bytes: Some(10_000),
+                    files: None,
+                }),
+                ..UserConfig::default()
+            };
+            config.users.insert(String::from("root"), user);
+        })
+        .await;
+        let mut client = server.login().await;
+        client.expect_command("TYPE I", "200").await;
+        client.stor("a.bin", &sample_data(300)).await;
+        fail_upload(&mut client, "a.bin").await;
+        fail_upload(&mut client, "b.bin").await;
+        let mut stored = client.retr("a.bin").await.len();
+        if atomic_uploads {
+            // Nothing of the failed uploads was kept.
+            assert_eq!(stored, 300);
+            assert!(!client.list("").await.contains("b.bin"));
+        } else {
+            stored += client.retr("b.bin").await.len();
+        }
+        let reply = client.expect_command("SITE QUOTA", "200").await;
+        let expected = format!("Bytes: {} of 10000 used.", stored);
+        assert!(reply.contains(&expected), "{}: {}", atomic_uploads, reply);
+    }
+}
+
+#[tokio::test]
+async fn file_quota_refuses_new_files() {
+    let server = quota_server(None, Some(2)).await;
+    let home = server.root.join("home/root");
+    std::fs::create_dir_all(&home).unwrap();
+    std::fs::write(home.join("old.txt"), b"old").unwrap();
+    let mut client = server.login().await;
+    client.stor("a.txt", b"a").await;
+    client.expect_command("PASV", "227").await;
+    client.expect_command("STOR b.txt", "552").await;
+    // Nothing was left waiting for a data connection.
+    client.expect_command("LIST", "425").await;
+    client.stor("a.txt", b"replaced").await;
+    client.upload("APPE a.txt", b" and appended").await;
+    assert_eq!(
+        std::fs::read(home.join("a.txt")).unwrap(),
+        b"replaced and appended"
+    );
+    client.expect_command("RNFR a.txt", "350").await;
+    client.expect_command("RNTO old.txt", "250").await;
+    client.stor("b.txt", b"b").await;
+}
+
+#[tokio::test]
+async fn quota_counts_only_the_home() {
+    let server = quota_server(Some(1000), Some(10)).await;
+    std::fs::write(server.root.join("outside.bin"), sample_data(5000)).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    // The home was created, and is all the user sees.
+    assert!(server.root.join("home/root").is_dir());
+    client.expect_command("CWD ..", "250").await;
+    assert!(!client.list("/").await.contains("outside.bin"));
+    client.stor("mine.bin", &sample_data(300)).await;
+    assert!(server.root.join("home/root/mine.bin").exists());
+    let reply = client.expect_command("SITE QUOTA", "200").await;
+    assert!(reply.contains("Bytes: 300 of 1000 used."), "{}", reply);
+    assert!(reply.contains("Files: 1 of 10 used."), "{}", reply);
+
+    // Uploads by another user only count when they land in the home.
+    let mut other = server.client().await;
+    other.login("anonymous", "guest").await;
+    other.expect_command("TYPE I", "200").await;
+    other.stor("elsewhere.bin", &sample_data(900)).await;
+    other.stor("home/root/shared.bin", &sample_data(200)).await;
+    let reply = client.expect_command("SITE QUOTA", "200").await;
+    assert!(reply.contains("Bytes: 500 of 1000 used."), "{}", reply);
+    assert!(reply.contains("Files: 2 of 10 used."), "{}", reply);
+    other
+        .expect_command("DELE home/root/shared.bin", "250")
+        .await;
+    client.stor("more.bin", &sample_data(700)).await;
+    let reply = client.expect_command("SITE QUOTA", "200").await;
+    assert!(reply.contains("Bytes: 1000 of 1000 used."), "{}", reply);
+}
+
+#[tokio::test]
+async fn quota_status() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    client
+        .expect_command("SITE QUOTA", "200 No quota for root.")
+        .await;
+    client.expect_command("SITE CHMOD 644 a", "504").await;
+    client.upload("APPE new.txt", b"new").await;
+    assert_eq!(std::fs::read(server.root.join("new.txt")).unwrap(), b"new");
+}
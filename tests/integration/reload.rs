@@ -0,0 +1,71 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{config, TestServer};
+use kiraftp::utils::config::{Config, Storage};
+
+#[tokio::test]
+async fn reload_applies_to_new_sessions() {
+    let server = TestServer::start().await;
+    let mut existing = server.login().await;
+    let config = Config {
+        username: "alice".to_string(),
+        password: "secret".to_string(),
+        ..server.config.clone()
+    };
+    server.reload.reload(config).unwrap();
+    let mut client = server.client().await;
+    client.expect_command("USER root", "331").await;
+    client.expect_command("PASS password", "530").await;
+    client.login("alice", "secret").await;
+    existing.expect_command("NOOP", "200").await;
+    existing.expect_command("PWD", "257").await;
+}
+
+#[tokio::test]
+async fn reload_switches_storage() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("local.txt"), b"local").unwrap();
+    let mut existing = server.login().await;
+    let config = Config {
+        storage: Storage::Memory { quota: None },
+        ..server.config.clone()
+    };
+    server.reload.reload(config).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("RETR local.txt", "550").await;
+    assert_eq!(existing.retr("local.txt").await, b"local");
+}
+
+#[tokio::test]
+async fn invalid_configs_are_refused() {
+    let server = TestServer::start().await;
+    let config = Config {
+        password: String::new(),
+        port: 0,
+        ..server.config.clone()
+    };
+    let err = server.reload.reload(config).unwrap_err();
+    assert!(err.to_string().contains("password:"), "{}", err);
+    assert!(err.to_string().contains("port:"), "{}", err);
+    let mut client = server.client().await;
+    client.login("root", "password").await;
+}
+
+#[test]
+fn changes_are_listed_with_secrets_redacted() {
+    let old = config("/srv".into());
+    assert!(old.changes(&old.clone()).is_empty());
+    let new = Config {
+        username: "alice".to_string(),
+        password: "secret".to_string(),
+        storage: Storage::Memory { quota: Some(1024) },
+        ..old.clone()
+    };
+    let changes = old.changes(&new);
+    assert!(changes.contains(&"username: root -> alice".to_string()));
+    assert!(changes.contains(&"password: (redacted) -> (redacted)".to_string()));
+    assert!(changes.contains(&"storage.type: local -> memory".to_string()));
+    assert!(changes.contains(&"storage.quota: (unset) -> 1024".to_string()));
+    assert!(!changes.iter().any(|change| change.contains("secret")));
+}
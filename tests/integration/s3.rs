@@ -0,0 +1,409 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Runs the S3 backend against a small in-process stand-in for an
+//! S3-compatible service, speaking just enough HTTP/1.1 for the client.
+
+use super::{sample_data, TestServer};
+use kiraftp::utils::config::{S3Config, Storage};
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// Keys per ListObjectsV2 page, small so that paging gets exercised.
+const PAGE_SIZE: usize = 3;
+
+#[derive(Default)]
+struct Bucket {
+    objects: BTreeMap<String, Vec<u8>>,
+    uploads: HashMap<String, BTreeMap<usize, Vec<u8>>>,
+    next_upload: usize,
+    parts_uploaded: usize,
+    aborted: usize,
+}
+
+struct Request {
+    method: String,
+    key: String,
+    query: HashMap<String, String>,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+struct Reply {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn new(status: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+}
+
+fn decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            decoded.push(u8::from_str_radix(&input[i + 1..i + 3], 16).unwrap());
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).unwrap()
+}
+
+fn list(bucket: &Bucket, query: &HashMap<String, String>) -> Reply {
+    let prefix = query.get("prefix").cloned().unwrap_or_default();
+    let delimited = query.get("delimiter").map(String::as_str) == Some("/");
+    let limit = query
+        .get("max-keys")
+        .map(|x| x.parse().unwrap())
+        .unwrap_or(1000)
+        .min(PAGE_SIZE);
+    let after = query.get("continuation-token").cloned().unwrap_or_default();
+    let mut entries = Vec::new();
+    for key in bucket.objects.keys().filter(|key| key.starts_with(&prefix)) {
+        let entry = match key[prefix.len()..].find('/') {
+            Some(slash) if delimited => key[..prefix.len() + slash + 1].to_string(),
+            _ => key.clone(),
+        };
+        if entry > after && entries.last() != Some(&entry) {
+            entries.push(entry);
+        }
+    }
+    let truncated = entries.len() > limit;
+    entries.truncate(limit);
+    let mut body = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?><ListBucketResult>");
+    for entry in &entries {
+        if entry.ends_with('/') && delimited && entry != &prefix {
+            body.push_str(&format!(
+                "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                entry
+            ));
+        } else {
+            body.push_str(&format!(
+                "<Contents><Key>{}</Key><LastModified>2021-09-01T12:00:00.000Z</LastModified>\
+                 <ETag>\"x\"</ETag><Size>{}</Size></Contents>",
+                entry,
+                bucket.objects[entry].len()
+            ));
+        }
+    }
+    body.push_str(&format!("<IsTruncated>{}</IsTruncated>", truncated));
+    if truncated {
+        body.push_str(&format!(
+            "<NextContinuationToken>{}</NextContinuationToken>",
+            entries.last().unwrap()
+        ));
+    }
+    body.push_str("</ListBucketResult>");
+    Reply::new("200 OK", body)
+}
+
+fn handle(bucket: &mut Bucket, request: Request) -> Reply {
+    let authorized = request
+        .headers
+        .get("authorization")
+        .map(|x| x.starts_with("AWS4-HMAC-SHA256 Credential=access/"))
+        .unwrap_or(false);
+    if !authorized || !request.headers.contains_key("x-amz-date") {
+        return Reply::new("403 Forbidden", "<Error><Code>AccessDenied</Code></Error>");
+    }
+    let query = &request.query;
+    let key = request.key;
+    match request.method.as_str() {
+        "GET" if key.is_empty() => list(bucket, query),
+        "GET" | "HEAD" => match bucket.objects.get(&key) {
+            Some(data) => {
+                let offset = request
+                    .headers
+                    .get("range")
+                    .map(|x| x["bytes=".len()..x.len() - 1].parse().unwrap())
+                    .unwrap_or(0);
+                let status = if offset > 0 {
+                    "206 Partial Content"
+                } else {
+                    "200 OK"
+                };
+                let mut reply = Reply::new(status, &data[offset..]);
+                reply.headers.push((
+                    "last-modified",
+                    String::from("Wed, 01 Sep 2021 12:00:00 GMT"),
+                ));
+                reply
+            }
+            None => Reply::new("404 Not Found", "<Error><Code>NoSuchKey</Code></Error>"),
+        },
+        "PUT" if query.contains_key("uploadId") => {
+            let part = query["partNumber"].parse().unwrap();
+            let upload = bucket.uploads.get_mut(&query["uploadId"]).unwrap();
+            upload.insert(part, request.body);
+            bucket.parts_uploaded += 1;
+            let mut reply = Reply::new("200 OK", "");
+            reply.headers.push(("etag", format!("\"etag-{}\"", part)));
+            reply
+        }
+        "PUT" => {
+            let data = match request.headers.get("x-amz-copy-source") {
+                Some(source) => {
+                    let source = decode(source);
+                    let source = source.splitn(3, '/').nth(2).unwrap();
+                    match bucket.objects.get(source) {
+                        Some(data) => data.clone(),
+                        None => return Reply::new("404 Not Found", ""),
+                    }
+                }
+                None => request.body,
+            };
+            bucket.objects.insert(key, data);
+            Reply::new("200 OK", "")
+        }
+        "POST" if query.contains_key("uploads") => {
+            bucket.next_upload += 1;
+            let id = format!("upload-{}", bucket.next_upload);
+            bucket.uploads.insert(id.clone(), BTreeMap::new());
+            Reply::new(
+                "200 OK",
+                format!(
+                    "<InitiateMultipartUploadResult><Bucket>bucket</Bucket><Key>{}</Key>\
+                     <UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                    key, id
+                ),
+            )
+        }
+        "POST" if query.contains_key("uploadId") => {
+            let parts = bucket.uploads.remove(&query["uploadId"]).unwrap();
+            let body = String::from_utf8(request.body).unwrap();
+            assert_eq!(body.matches("<Part>").count(), parts.len());
+            bucket
+                .objects
+                .insert(key, parts.into_values().flatten().collect());
+            Reply::new("200 OK", "<CompleteMultipartUploadResult/>")
+        }
+        "DELETE" if query.contains_key("uploadId") => {
+            bucket.uploads.remove(&query["uploadId"]);
+            bucket.aborted += 1;
+            Reply::new("204 No Content", "")
+        }
+        "DELETE" => {
+            bucket.objects.remove(&key);
+            Reply::new("204 No Content", "")
+        }
+        _ => Reply::new("400 Bad Request", ""),
+    }
+}
+
+async fn serve_connection(stream: TcpStream, bucket: Arc<Mutex<Bucket>>) -> Option<()> {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        let mut parts = line.split_whitespace();
+        let method = parts.next()?.to_string();
+        let target = parts.next()?.to_string();
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.ok()?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':')?;
+            headers.insert(name.to_ascii_lowercase(), value.trim().to_string());
+        }
+        let length = headers
+            .get("content-length")
+            .map(|x| x.parse().unwrap())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.ok()?;
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+        let key = decode(path.trim_start_matches("/bucket").trim_start_matches('/'));
+        let query = query
+            .split('&')
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                let (name, value) = x.split_once('=').unwrap_or((x, ""));
+                (decode(name), decode(value))
+            })
+            .collect();
+        let head = method == "HEAD";
+        let reply = handle(
+            &mut bucket.lock().unwrap(),
+            Request {
+                method,
+                key,
+                query,
+                headers,
+                body,
+            },
+        );
+        let mut response = format!(
+            "HTTP/1.1 {}\r\ncontent-length: {}\r\n",
+            reply.status,
+            reply.body.len()
+        );
+        for (name, value) in reply.headers {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str("\r\n");
+        let stream = stream.get_mut();
+        stream.write_all(response.as_bytes()).await.ok()?;
+        if !head {
+            stream.write_all(&reply.body).await.ok()?;
+        }
+    }
+}
+
+async fn start_bucket() -> (SocketAddr, Arc<Mutex<Bucket>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let bucket = Arc::new(Mutex::new(Bucket::default()));
+    let shared = bucket.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve_connection(stream, shared.clone()));
+        }
+    });
+    (addr, bucket)
+}
+
+async fn s3_server() -> (TestServer, Arc<Mutex<Bucket>>) {
+    let (addr, bucket) = start_bucket().await;
+    let server = TestServer::with_config(|config| {
+        config.storage = Storage::S3(S3Config {
+            endpoint: format!("http://{}", addr),
+            bucket: String::from("bucket"),
+            region: String::from("us-east-1"),
+            access_key: String::from("access"),
+            secret_key: String::from("secret"),
+            prefix: String::from("ftp"),
+            path_style: true,
+            part_size: 64 << 10,
+        })
+    })
+    .await;
+    (server, bucket)
+}
+
+#[tokio::test]
+async fn files_map_to_objects() {
+    let (server, bucket) = s3_server().await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("hello world.txt", b"hello").await;
+    assert_eq!(
+        bucket.lock().unwrap().objects["ftp/hello world.txt"],
+        b"hello"
+    );
+    assert_eq!(client.retr("hello world.txt").await, b"hello");
+    client.expect_command("MKD dir", "257").await;
+    client.expect_command("MKD dir", "550").await;
+    assert!(bucket.lock().unwrap().objects.contains_key("ftp/dir/"));
+    client.stor("dir/a.txt", b"a").await;
+    let _data_stream = client.pasv().await;
+    client.expect_command("STOR missing/a.txt", "553").await;
+    let listing = client.list("").await;
+    let dir = listing.lines().find(|x| x.ends_with(" dir")).unwrap();
+    assert!(dir.starts_with('d'), "{}", listing);
+    let file = listing.lines().find(|x| x.ends_with("'hello world.txt'"));
+    assert!(file.unwrap().contains(" 5 "), "{}", listing);
+    client.expect_command("CWD dir", "250").await;
+    assert!(client.list("").await.ends_with(" a.txt\r\n"));
+    client.expect_command("CWD /missing", "550").await;
+    client.expect_command("RMD /dir", "550").await;
+    client.expect_command("RNFR a.txt", "350").await;
+    client.expect_command("RNTO /b.txt", "250").await;
+    client.expect_command("RMD /dir", "250").await;
+    client.expect_command("DELE /b.txt", "250").await;
+    let objects: Vec<String> = bucket.lock().unwrap().objects.keys().cloned().collect();
+    assert_eq!(objects, ["ftp/hello world.txt"]);
+}
+
+#[tokio::test]
+async fn directories_are_renamed_with_their_contents() {
+    let (server, bucket) = s3_server().await;
+    let mut client = server.login().await;
+    client.expect_command("MKD a", "257").await;
+    for name in ["1", "2", "3", "4", "5"] {
+        client.stor(&format!("a/{}", name), name.as_bytes()).await;
+    }
+    client.expect_command("RNFR a", "350").await;
+    client.expect_command("RNTO b", "250").await;
+    let names: Vec<String> = client
+        .list("b")
+        .await
+        .lines()
+        .map(|x| x.rsplit(' ').next().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["1", "2", "3", "4", "5"]);
+    assert!(!bucket
+        .lock()
+        .unwrap()
+        .objects
+        .keys()
+        .any(|x| x.starts_with("ftp/a/")));
+}
+
+#[tokio::test]
+async fn large_files_use_multipart_upload() {
+    let (server, bucket) = s3_server().await;
+    let content = sample_data(300 << 10);
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("big.bin", &content).await;
+    {
+        let bucket = bucket.lock().unwrap();
+        assert_eq!(bucket.parts_uploaded, 5);
+        assert_eq!(bucket.objects["ftp/big.bin"], content);
+    }
+    assert_eq!(client.retr("big.bin").await, content);
+}
+
+#[tokio::test]
+async fn failed_upload_stores_nothing() {
+    let (server, bucket) = s3_server().await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    let data_stream = client.pasv().await;
+    client.send(b"STOR big.bin\r\n").await;
+    client.expect("150").await;
+    let mut data_stream = data_stream;
+    data_stream
+        .write_all(&sample_data(200 << 10))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // Reset the connection instead of closing it, so the upload fails. A zero
+    // linger never blocks on drop, which is what the deprecation is about.
+    #[allow(deprecated)]
+    data_stream.set_linger(Some(Duration::ZERO)).unwrap();
+    drop(data_stream);
+    client.expect("426").await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let bucket = bucket.lock().unwrap();
+    assert!(bucket.objects.is_empty());
+    assert!(bucket.uploads.is_empty());
+    assert_eq!(bucket.aborted, 1);
+}
@@ -0,0 +1,106 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{config, sample_data, temp_dir, Client, TestServer};
+use kiraftp::{utils::config::Config, Server};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+#[tokio::test]
+async fn builder_binds_config_address() {
+    let config = Config {
+        listen: [127, 0, 0, 1].into(),
+        port: 0,
+        ..config(temp_dir())
+    };
+    let server = Server::builder().config(config).build().await.unwrap();
+    let addr = server.local_addr().unwrap();
+    assert_ne!(addr.port(), 0);
+    tokio::spawn(server.run());
+    let mut client = Client::new(TcpStream::connect(addr).await.unwrap()).await;
+    client.login("root", "password").await;
+}
+
+#[tokio::test]
+async fn builder_needs_credentials() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let err = Server::builder().listener(listener).build().await.err();
+    assert_eq!(err.unwrap().kind(), std::io::ErrorKind::InvalidInput);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = Config {
+        path: temp_dir(),
+        ..Config::default()
+    };
+    let err = Server::builder()
+        .config(config)
+        .listener(listener)
+        .build()
+        .await
+        .err();
+    assert_eq!(err.unwrap().kind(), std::io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn shutdown_stops_accepting() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder()
+        .config(config(temp_dir()))
+        .listener(listener)
+        .build()
+        .await
+        .unwrap();
+    let shutdown = server.shutdown_handle();
+    let handle = tokio::spawn(server.run());
+    let mut client = Client::new(TcpStream::connect(addr).await.unwrap()).await;
+    client.expect_command("NOOP", "200").await;
+    shutdown.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(TcpStream::connect(addr).await.is_err());
+    client.expect("421").await;
+}
+
+#[tokio::test]
+async fn shutdown_lets_transfers_finish() {
+    let mut server = TestServer::start().await;
+    let content = sample_data(8 << 20);
+    std::fs::write(server.root.join("big.bin"), &content).unwrap();
+    let mut client = server.login().await;
+    let mut idle = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"RETR big.bin\r\nNOOP\r\n").await;
+    client.expect("150").await;
+    server.shutdown.shutdown();
+    idle.expect("421").await;
+    let mut received = Vec::new();
+    data_stream.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, content);
+    client.expect("226").await;
+    // Pipelined commands are not run once shutdown has started.
+    client.expect("421").await;
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn shutdown_aborts_transfers_after_deadline() {
+    let mut server = TestServer::with_config(|config| config.shutdown_timeout = 1).await;
+    let mut client = server.login().await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"STOR slow.bin\r\n").await;
+    client.expect("150").await;
+    data_stream.write_all(b"partial").await.unwrap();
+    let started = std::time::Instant::now();
+    tokio::time::timeout(Duration::from_secs(5), server.stop())
+        .await
+        .unwrap();
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    let mut rest = Vec::new();
+    assert_eq!(data_stream.read_to_end(&mut rest).await.unwrap(), 0);
+}
@@ -0,0 +1,103 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{config, temp_dir, Client};
+use async_trait::async_trait;
+use kiraftp::{
+    storage::{
+        Chroot, DirEntry, LocalFileSystem, Metadata, ReadStream, StorageBackend, WriteStream,
+    },
+    Server,
+};
+use std::{io::Error, path::Path, sync::Arc};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Serves a local directory but refuses every change.
+struct ReadOnly(LocalFileSystem);
+
+fn denied() -> Error {
+    Error::from(std::io::ErrorKind::PermissionDenied)
+}
+
+#[async_trait]
+impl StorageBackend for ReadOnly {
+    async fn stat(&self, path: &Path) -> tokio::io::Result<Metadata> {
+        self.0.stat(path).await
+    }
+
+    async fn list(&self, path: &Path) -> tokio::io::Result<Vec<DirEntry>> {
+        self.0.list(path).await
+    }
+
+    async fn open_read(&self, path: &Path, offset: u64) -> tokio::io::Result<ReadStream> {
+        self.0.open_read(path, offset).await
+    }
+
+    async fn open_write(&self, _: &Path, _: u64) -> tokio::io::Result<WriteStream> {
+        Err(denied())
+    }
+
+    async fn mkdir(&self, _: &Path) -> tokio::io::Result<()> {
+        Err(denied())
+    }
+
+    async fn remove(&self, _: &Path) -> tokio::io::Result<()> {
+        Err(denied())
+    }
+
+    async fn rename(&self, _: &Path, _: &Path) -> tokio::io::Result<()> {
+        Err(denied())
+    }
+}
+
+#[tokio::test]
+async fn custom_backend() {
+    let root = temp_dir();
+    std::fs::write(root.join("a.txt"), b"hello").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder()
+        .config(config(root.clone()))
+        .listener(listener)
+        .storage(Arc::new(ReadOnly(LocalFileSystem::new(&root))))
+        .build()
+        .await
+        .unwrap();
+    tokio::spawn(server.run());
+    let mut client = Client::new(TcpStream::connect(addr).await.unwrap()).await;
+    client.login("root", "password").await;
+    assert_eq!(client.retr("a.txt").await, b"hello");
+    assert!(client.list("").await.contains("a.txt"));
+    let _data_stream = client.pasv().await;
+    client.expect_command("STOR b.txt", "553").await;
+    client.expect_command("MKD dir", "550").await;
+    client.expect_command("DELE a.txt", "550").await;
+    client.expect_command("RNFR a.txt", "350").await;
+    client.expect_command("RNTO b.txt", "550").await;
+    assert!(!root.join("b.txt").exists());
+}
+
+#[tokio::test]
+async fn chroot_serves_a_directory_as_root() {
+    let root = temp_dir();
+    std::fs::create_dir(root.join("sub")).unwrap();
+    std::fs::write(root.join("secret.txt"), b"secret").unwrap();
+    let inner: Arc<dyn StorageBackend> = Arc::new(LocalFileSystem::new(&root));
+    let chroot = Chroot::new(inner, "/sub");
+    chroot.mkdir(Path::new("/dir")).await.unwrap();
+    assert!(root.join("sub/dir").is_dir());
+    chroot
+        .rename(Path::new("/dir"), Path::new("/moved"))
+        .await
+        .unwrap();
+    assert!(root.join("sub/moved").is_dir());
+    let names: Vec<String> = chroot
+        .list(Path::new("/.."))
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|x| x.name)
+        .collect();
+    assert_eq!(names, ["moved"]);
+    assert!(chroot.stat(Path::new("../secret.txt")).await.is_err());
+}
@@ -0,0 +1,206 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{sample_data, TestServer};
+use kiraftp::utils::config::ActiveMode;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[tokio::test]
+async fn retr_binary_passive() {
+    let server = TestServer::start().await;
+    let content = sample_data(200_000);
+    std::fs::write(server.root.join("data.bin"), &content).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    assert_eq!(client.retr("data.bin").await, content);
+}
+
+#[tokio::test]
+async fn retr_binary_active() {
+    let server = TestServer::start().await;
+    let content = sample_data(200_000);
+    std::fs::write(server.root.join("data.bin"), &content).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    let listener = client.port().await;
+    client.send(b"RETR data.bin\r\n").await;
+    let (mut data_stream, _) = listener.accept().await.unwrap();
+    let mut received = Vec::new();
+    data_stream.read_to_end(&mut received).await.unwrap();
+    client.expect("150").await;
+    client.expect("226").await;
+    assert_eq!(received, content);
+}
+
+#[tokio::test]
+async fn active_connects_from_control_address() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("a.txt"), b"data").unwrap();
+    let mut client = server.login().await;
+    let listener = client.port().await;
+    client.send(b"RETR a.txt\r\n").await;
+    let (_data_stream, source) = listener.accept().await.unwrap();
+    client.expect("150").await;
+    client.expect("226").await;
+    assert_eq!(source.ip(), server.addr.ip());
+    assert_ne!(source.port(), server.addr.port() - 1);
+}
+
+#[tokio::test]
+async fn strict_active_mode_is_shared_by_sessions() {
+    let server = TestServer::with_config(|config| config.active_mode = ActiveMode::Strict).await;
+    std::fs::write(server.root.join("a.txt"), b"data").unwrap();
+    let mut first = server.login().await;
+    let mut second = server.login().await;
+    let first_listener = first.port().await;
+    let second_listener = second.port().await;
+    first.send(b"RETR a.txt\r\n").await;
+    second.send(b"RETR a.txt\r\n").await;
+    let (mut first_stream, first_source) = first_listener.accept().await.unwrap();
+    let (mut second_stream, second_source) = second_listener.accept().await.unwrap();
+    for source in [first_source, second_source] {
+        assert_eq!(source.port(), server.addr.port() - 1);
+    }
+    let mut received = Vec::new();
+    first_stream.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, b"data");
+    received.clear();
+    second_stream.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received, b"data");
+    for client in [&mut first, &mut second] {
+        client.expect("150").await;
+        client.expect("226").await;
+    }
+}
+
+#[tokio::test]
+async fn stor_binary_passive() {
+    let server = TestServer::start().await;
+    let content = sample_data(200_000);
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("data.bin", &content).await;
+    assert_eq!(
+        std::fs::read(server.root.join("data.bin")).unwrap(),
+        content
+    );
+}
+
+#[tokio::test]
+async fn stor_binary_active() {
+    let server = TestServer::start().await;
+    let content = sample_data(200_000);
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    let listener = client.port().await;
+    client.send(b"STOR data.bin\r\n").await;
+    let (mut data_stream, _) = listener.accept().await.unwrap();
+    client.expect("150").await;
+    data_stream.write_all(&content).await.unwrap();
+    drop(data_stream);
+    client.expect("226").await;
+    assert_eq!(
+        std::fs::read(server.root.join("data.bin")).unwrap(),
+        content
+    );
+}
+
+#[tokio::test]
+async fn stor_replaces_longer_file() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("data.bin"), sample_data(1000)).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("data.bin", b"short").await;
+    assert_eq!(
+        std::fs::read(server.root.join("data.bin")).unwrap(),
+        b"short"
+    );
+}
+
+#[tokio::test]
+async fn stor_without_data_connection_leaves_file_intact() {
+    let server = TestServer::start().await;
+    std::fs::write(server.root.join("keep.txt"), b"keep").unwrap();
+    let mut client = server.login().await;
+    client
+        .expect_command("STOR keep.txt", "425 Use PORT or PASV first.")
+        .await;
+    // Nobody listens on the port once the listener is gone.
+    drop(client.port().await);
+    client.expect_command("STOR keep.txt", "425").await;
+    assert_eq!(
+        std::fs::read(server.root.join("keep.txt")).unwrap(),
+        b"keep"
+    );
+    assert!(!server.root.join("new.txt").exists());
+    client.expect_command("STOR new.txt", "425").await;
+    assert!(!server.root.join("new.txt").exists());
+}
+
+#[tokio::test]
+async fn retr_ascii_converts_line_endings() {
+    let server = TestServer::start().await;
+    let mut content = Vec::new();
+    for line in 0..10_000 {
+        content.extend_from_slice(format!("line {}\n", line).as_bytes());
+    }
+    std::fs::write(server.root.join("text.txt"), &content).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("TYPE A", "200").await;
+    let received = client.retr("text.txt").await;
+    let expected = String::from_utf8(content).unwrap().replace('\n', "\r\n");
+    assert_eq!(String::from_utf8(received).unwrap(), expected);
+}
+
+#[tokio::test]
+async fn stor_ascii_converts_line_endings() {
+    let server = TestServer::start().await;
+    let mut content = String::new();
+    for line in 0..10_000 {
+        content.push_str(&format!("line {}\r\n", line));
+    }
+    content.push_str("bare\rcarriage\r");
+    let mut client = server.login().await;
+    client.expect_command("TYPE A", "200").await;
+    client.stor("text.txt", content.as_bytes()).await;
+    let stored = std::fs::read_to_string(server.root.join("text.txt")).unwrap();
+    assert_eq!(stored, content.replace("\r\n", "\n"));
+}
+
+#[tokio::test]
+async fn stor_ascii_joins_crlf_split_across_reads() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE A", "200").await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"STOR text.txt\r\n").await;
+    client.expect("150").await;
+    data_stream.write_all(b"one\r").await.unwrap();
+    data_stream.flush().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    data_stream.write_all(b"\ntwo\r").await.unwrap();
+    data_stream.flush().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    data_stream.write_all(b"three").await.unwrap();
+    drop(data_stream);
+    client.expect("226").await;
+    assert_eq!(
+        std::fs::read(server.root.join("text.txt")).unwrap(),
+        b"one\ntwo\rthree"
+    );
+}
+
+#[tokio::test]
+async fn stor_then_retr_in_subdirectory() {
+    let server = TestServer::start().await;
+    std::fs::create_dir(server.root.join("sub")).unwrap();
+    let content = sample_data(5000);
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("sub/data.bin", &content).await;
+    client.expect_command("CWD sub", "250").await;
+    client.expect_command("PWD", "257 \"/sub\"").await;
+    assert_eq!(client.retr("data.bin").await, content);
+    assert_eq!(client.retr("/sub/data.bin").await, content);
+}
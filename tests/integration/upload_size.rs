@@ -0,0 +1,136 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{sample_data, Client, TestServer};
+use kiraftp::utils::config::UserConfig;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// Send `len` bytes with `command`, expecting the upload to be stopped. The
+/// data goes in two halves, so that the first may be stored.
+async fn upload_too_much(client: &mut Client, command: &str, len: usize) {
+    let mut data_stream = client.pasv().await;
+    client.send(format!("{}\r\n", command).as_bytes()).await;
+    client.expect("150").await;
+    let data = sample_data(len);
+    data_stream.write_all(&data[..len / 2]).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _ = data_stream.write_all(&data[len / 2..]).await;
+    drop(data_stream);
+    client
+        .expect("552 File exceeds the maximum upload size.")
+        .await;
+}
+
+#[tokio::test]
+async fn large_uploads_are_stopped() {
+    let server = TestServer::with_config(|config| config.max_upload_size = Some(1000)).await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("a.bin", &sample_data(1000)).await;
+    upload_too_much(&mut client, "STOR b.bin", 1500).await;
+    assert!(!server.root.join("b.bin").exists());
+    // Appending counts what the file holds already.
+    client.stor("c.bin", &sample_data(600)).await;
+    upload_too_much(&mut client, "APPE c.bin", 500).await;
+    assert_eq!(
+        std::fs::read(server.root.join("c.bin")).unwrap(),
+        sample_data(600)
+    );
+    client.upload("APPE c.bin", &sample_data(400)).await;
+}
+
+#[tokio::test]
+async fn stopped_replacements_leave_nothing() {
+    let server = TestServer::with_config(|config| {
+        config.max_upload_size = Some(1000);
+        config.atomic_uploads = false;
+    })
+    .await;
+    std::fs::write(server.root.join("a.bin"), sample_data(600)).unwrap();
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    upload_too_much(&mut client, "STOR a.bin", 1500).await;
+    assert!(!server.root.join("a.bin").exists());
+}
+
+#[tokio::test]
+async fn stopped_resumes_keep_the_file() {
+    let server = TestServer::with_config(|config| config.max_upload_size = Some(15)).await;
+    std::fs::write(server.root.join("a.bin"), b"0123456789").unwrap();
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.expect_command("REST 10", "350").await;
+    upload_too_much(&mut client, "STOR a.bin", 20).await;
+    assert_eq!(
+        std::fs::read(server.root.join("a.bin")).unwrap(),
+        b"0123456789"
+    );
+    client.expect_command("REST 5", "350").await;
+    client.stor("a.bin", b"abcde").await;
+    assert_eq!(
+        std::fs::read(server.root.join("a.bin")).unwrap(),
+        b"01234abcde"
+    );
+}
+
+#[tokio::test]
+async fn atomic_uploads_leave_nothing_behind() {
+    let server = TestServer::with_config(|config| {
+        config.max_upload_size = Some(1000);
+        config.atomic_uploads = true;
+        config.keep_partial_uploads = true;
+    })
+    .await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    upload_too_much(&mut client, "STOR a.bin", 50_000).await;
+    assert_eq!(std::fs::read_dir(&server.root).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn allo_checks_declared_sizes() {
+    let server = TestServer::with_config(|config| config.max_upload_size = Some(1000)).await;
+    let mut client = server.login().await;
+    client.expect_command("ALLO 2000", "552").await;
+    client.expect_command("ALLO many", "501").await;
+    client
+        .expect_command("ALLO 1000 R 100", "200 Allocated 1000 bytes.")
+        .await;
+    client.stor("a.txt", b"a").await;
+    client.expect_command("ALLO 1000", "200").await;
+    client.expect_command("PASV", "227").await;
+    client.expect_command("APPE a.txt", "552").await;
+    // Nothing was left waiting for a data connection.
+    client.expect_command("LIST", "425").await;
+    // The declared size only applies to one upload.
+    client.upload("APPE a.txt", b"b").await;
+    assert_eq!(std::fs::read(server.root.join("a.txt")).unwrap(), b"ab");
+}
+
+#[tokio::test]
+async fn users_can_have_their_own_limit() {
+    let server = TestServer::with_config(|config| {
+        config.max_upload_size = Some(100);
+        let user = UserConfig {
+            max_upload_size: Some(1000),
+            ..UserConfig::default()
+        };
+        config.users.insert(String::from("root"), user);
+    })
+    .await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.expect_command("ALLO 1000", "200").await;
+    client.stor("a.bin", &sample_data(1000)).await;
+    upload_too_much(&mut client, "STOR b.bin", 1001).await;
+}
+
+#[tokio::test]
+async fn no_limit_by_default() {
+    let server = TestServer::start().await;
+    let mut client = server.login().await;
+    client.expect_command("ALLO 1000000000000", "200").await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("a.bin", &sample_data(100_000)).await;
+}
@@ -0,0 +1,88 @@
+// Copyright 2021 Slowy <slowyfine@gmail.com>
+// SPDX-License-Identifier: GPL-3.0-only
+
+use super::{sample_data, TestServer};
+use kiraftp::utils::config::Storage;
+use tokio::io::AsyncWriteExt;
+
+/// The fields following the five of the timestamp.
+fn fields(line: &str) -> Vec<&str> {
+    line.split_whitespace().skip(5).collect()
+}
+
+#[tokio::test]
+async fn transfers_are_logged() {
+    let mut xferlog = None;
+    let server = TestServer::with_config(|config| {
+        let path = config.path.with_file_name("xferlog");
+        config.xferlog = Some(path.clone());
+        xferlog = Some(path);
+    })
+    .await;
+    let xferlog = xferlog.unwrap();
+    let content = "line\n".repeat(1000);
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    client.stor("my file.bin", content.as_bytes()).await;
+    client.expect_command("TYPE A", "200").await;
+    assert_eq!(
+        client.retr("my file.bin").await,
+        "line\r\n".repeat(1000).as_bytes()
+    );
+    let log = std::fs::read_to_string(&xferlog).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", log);
+    assert_eq!(
+        fields(lines[0]),
+        [
+            "1",
+            "127.0.0.1",
+            "5000",
+            "/my_file.bin",
+            "b",
+            "_",
+            "i",
+            "r",
+            "root",
+            "ftp",
+            "0",
+            "*",
+            "c"
+        ]
+    );
+    let retr = fields(lines[1]);
+    assert_eq!(retr[2], "6000");
+    assert_eq!(retr[4], "a");
+    assert_eq!(retr[6], "o");
+    assert_eq!(retr[12], "c");
+    // The timestamp looks like "Mon Oct 19 07:42:50 2026".
+    let stamp: Vec<&str> = lines[0].split_whitespace().take(5).collect();
+    assert_eq!(stamp[0].len(), 3);
+    assert_eq!(stamp[3].len(), 8);
+    assert_eq!(stamp[4].len(), 4);
+}
+
+#[tokio::test]
+async fn aborted_transfers_are_logged() {
+    let mut xferlog = None;
+    let server = TestServer::with_config(|config| {
+        let path = config.path.with_file_name("xferlog");
+        config.xferlog = Some(path.clone());
+        config.storage = Storage::Memory { quota: Some(1000) };
+        xferlog = Some(path);
+    })
+    .await;
+    let mut client = server.login().await;
+    client.expect_command("TYPE I", "200").await;
+    let mut data_stream = client.pasv().await;
+    client.send(b"STOR big.bin\r\n").await;
+    client.expect("150").await;
+    let _ = data_stream.write_all(&sample_data(5000)).await;
+    drop(data_stream);
+    client.expect("426").await;
+    client.expect_command("NOOP", "200").await;
+    let log = std::fs::read_to_string(xferlog.unwrap()).unwrap();
+    let fields = fields(log.lines().next().unwrap());
+    assert_eq!(fields[3], "/big.bin");
+    assert_eq!(fields[12], "i");
+}